@@ -1,14 +1,29 @@
 fprintf("Benchmark of multiple, parallel SwE computations.\n");
-fprintf("Loading mock data from mock-data/*.npy...");
+% MAT-file written by `mock-npz --mat`, or else a directory of npy files
+% written by `mock-npz --npy-dir`. Set mockDataDir before running this script
+% to load data from somewhere else.
+if ~exist("mockDataDir", "var")
+    mockDataDir = "../mock-data";
+end
+if isfile(mockDataDir + ".mat")
+    fprintf("Loading mock data from %s.mat...", mockDataDir);
+    mockData = load(mockDataDir + ".mat");
+    pinvDesignMtx = mockData.x_pinv;
+    residual = mockData.resid;
+    groupIds = mockData.block_ids;
+    numGroupIDs = mockData.n_blocks;
+else
+    fprintf("Loading mock data from %s/*.npy...", mockDataDir);
 
-% Add path for loading npy files.
-addpath npy-matlab/npy-matlab/
+    % Add path for loading npy files.
+    addpath npy-matlab/npy-matlab/
 
-% Load mock data.
-pinvDesignMtx = readNPY("../mock-data/x_pinv.npy");
-residual = readNPY("../mock-data/resid.npy");
-groupIds = readNPY("../mock-data/block_ids.npy");
-numGroupIDs = readNPY("../mock-data/n_blocks.npy");
+    % Load mock data.
+    pinvDesignMtx = readNPY(fullfile(mockDataDir, "x_pinv.npy"));
+    residual = readNPY(fullfile(mockDataDir, "resid.npy"));
+    groupIds = readNPY(fullfile(mockDataDir, "block_ids.npy"));
+    numGroupIDs = readNPY(fullfile(mockDataDir, "n_blocks.npy"));
+end
 fprintf(" done.\n");
 
 % Number of repetitions.
@@ -0,0 +1,208 @@
+//! Analytic multiple-comparison adjustment of per-feature p-values.
+//!
+//! These adjustments are cheap alternatives to the resampling-based FWER
+//! correction in [`crate::maxstat`]. Each operates on a vector of `n_feat`
+//! p-values, such as those from the SwE t or Wald tests, and returns a vector
+//! of adjusted p-values (or q-values) in the same order.
+//!
+//! NaN p-values are passed through as NaN and are not counted towards the
+//! number of tests. Tied p-values always receive identical adjusted values.
+
+use ndarray::{Array, ArrayView, Dim};
+
+/// Multiple-comparison adjustment method.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Adjustment {
+    /// Bonferroni family-wise error rate correction.
+    Bonferroni,
+    /// Holm's step-down family-wise error rate correction.
+    Holm,
+    /// Benjamini-Hochberg false discovery rate for independent or positively
+    /// dependent tests.
+    BenjaminiHochberg,
+    /// Benjamini-Yekutieli false discovery rate under arbitrary dependence.
+    BenjaminiYekutieli,
+    /// Storey q-values, estimating the proportion of true null hypotheses
+    /// from p-values greater than the enclosed tuning parameter lambda. Lambda
+    /// must be at least zero and less than one; 0.5 is a common choice.
+    Storey(f64),
+}
+impl std::fmt::Display for Adjustment {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Adjustment::Bonferroni => write!(f, "Bonferroni"),
+            Adjustment::Holm => write!(f, "Holm"),
+            Adjustment::BenjaminiHochberg => write!(f, "Benjamini-Hochberg"),
+            Adjustment::BenjaminiYekutieli => write!(f, "Benjamini-Yekutieli"),
+            Adjustment::Storey(lambda) => write!(f, "Storey (lambda = {})", lambda),
+        }
+    }
+}
+
+/// Adjust p-values for multiple comparisons using the given method.
+///
+/// Panics if the lambda parameter for [`Adjustment::Storey`] is not in the
+/// interval `[0, 1)`.
+///
+/// Example:
+/// ```
+/// use ndarray::array;
+/// use swe_mockup::adjust::{adjust, Adjustment};
+/// let p = array![0.01, 0.04, 0.03, f64::NAN];
+/// let p_adj = adjust(p.view(), Adjustment::BenjaminiHochberg);
+/// assert_eq!(p_adj[0], 0.03);
+/// assert!(p_adj[3].is_nan());
+/// ```
+pub fn adjust(p: ArrayView<f64, Dim<[usize; 1]>>, method: Adjustment) -> Array<f64, Dim<[usize; 1]>> {
+    match method {
+        Adjustment::Bonferroni => bonferroni(p),
+        Adjustment::Holm => holm(p),
+        Adjustment::BenjaminiHochberg => benjamini_hochberg(p),
+        Adjustment::BenjaminiYekutieli => benjamini_yekutieli(p),
+        Adjustment::Storey(lambda) => storey(p, lambda),
+    }
+}
+
+/// Bonferroni-adjusted p-values, `min(1, m * p)` for `m` non-NaN p-values.
+/// NaN p-values stay NaN.
+pub fn bonferroni(p: ArrayView<f64, Dim<[usize; 1]>>) -> Array<f64, Dim<[usize; 1]>> {
+    let m = p.iter().filter(|x| !x.is_nan()).count() as f64;
+    // f64::min returns the other operand for NaN, which would turn NaN into 1.
+    p.mapv(|x| if x.is_nan() { x } else { (x * m).min(1.) })
+}
+
+/// Holm step-down adjusted p-values.
+pub fn holm(p: ArrayView<f64, Dim<[usize; 1]>>) -> Array<f64, Dim<[usize; 1]>> {
+    let order = ascending_order(p);
+    let m = order.len();
+    let mut p_adj = Array::from_elem(p.len(), f64::NAN);
+    // Walk up from the smallest p-value, taking the cumulative maximum.
+    let mut p_max: f64 = 0.;
+    for (rank, &i) in order.iter().enumerate() {
+        p_max = p_max.max(p[i] * (m - rank) as f64).min(1.);
+        p_adj[i] = p_max;
+    }
+    p_adj
+}
+
+/// Benjamini-Hochberg adjusted p-values.
+pub fn benjamini_hochberg(p: ArrayView<f64, Dim<[usize; 1]>>) -> Array<f64, Dim<[usize; 1]>> {
+    step_up(p, 1.)
+}
+
+/// Benjamini-Yekutieli adjusted p-values.
+pub fn benjamini_yekutieli(p: ArrayView<f64, Dim<[usize; 1]>>) -> Array<f64, Dim<[usize; 1]>> {
+    let m = p.iter().filter(|x| !x.is_nan()).count();
+    // Harmonic number correction for arbitrary dependence.
+    let c_m = (1..=m).map(|i| 1. / i as f64).sum();
+    step_up(p, c_m)
+}
+
+/// Storey q-values with tuning parameter `lambda`.
+///
+/// The proportion of true null hypotheses is estimated as
+/// `#{p > lambda} / (m * (1 - lambda))`, capped at one. At least one p-value
+/// is always counted as null so that the estimate is never zero.
+///
+/// Panics if `lambda` is not in the interval `[0, 1)`.
+pub fn storey(p: ArrayView<f64, Dim<[usize; 1]>>, lambda: f64) -> Array<f64, Dim<[usize; 1]>> {
+    assert!(
+        (0. ..1.).contains(&lambda),
+        "Storey lambda must be in [0, 1) but is {}.",
+        lambda
+    );
+    let m = p.iter().filter(|x| !x.is_nan()).count();
+    let n_null = p.iter().filter(|&&x| x > lambda).count().max(1);
+    let pi0 = f64::min(1., n_null as f64 / (m as f64 * (1. - lambda)));
+    let mut q = benjamini_hochberg(p);
+    q.mapv_inplace(|x| x * pi0);
+    q
+}
+
+// Indices of the non-NaN p-values sorted in ascending order. The sort is
+// stable, although ties are resolved identically regardless of their order.
+fn ascending_order(p: ArrayView<f64, Dim<[usize; 1]>>) -> Vec<usize> {
+    let mut order: Vec<usize> = (0..p.len()).filter(|&i| !p[i].is_nan()).collect();
+    order.sort_by(|&a, &b| p[a].partial_cmp(&p[b]).unwrap());
+    order
+}
+
+// Benjamini-Hochberg style step-up adjustment, `c * m * p / rank`, with the
+// cumulative minimum taken from the largest p-value downward.
+fn step_up(p: ArrayView<f64, Dim<[usize; 1]>>, c: f64) -> Array<f64, Dim<[usize; 1]>> {
+    let order = ascending_order(p);
+    let m = order.len() as f64;
+    let mut p_adj = Array::from_elem(p.len(), f64::NAN);
+    let mut p_min: f64 = 1.;
+    for (rank, &i) in order.iter().enumerate().rev() {
+        p_min = p_min.min(c * m * p[i] / (rank + 1) as f64);
+        p_adj[i] = p_min;
+    }
+    p_adj
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ndarray::array;
+
+    // Five p-values with a tie at 0.04, and a NaN. Sorted, they are 0.01,
+    // 0.03, 0.04, 0.04 and 0.5.
+    fn p() -> Array<f64, Dim<[usize; 1]>> {
+        array![0.01, 0.04, 0.03, f64::NAN, 0.04, 0.5]
+    }
+
+    // Compare adjusted p-values, which must be NaN exactly where expected.
+    fn assert_close(p_adj: Array<f64, Dim<[usize; 1]>>, expected: Array<f64, Dim<[usize; 1]>>) {
+        assert_eq!(p_adj.len(), expected.len());
+        for (&a, &e) in p_adj.iter().zip(expected.iter()) {
+            assert!(a.is_nan() && e.is_nan() || (a - e).abs() < 1e-12, "{} != {}", p_adj, expected);
+        }
+    }
+
+    #[test]
+    fn bonferroni_passes_nan_through_and_caps_at_one() {
+        assert_close(bonferroni(p().view()), array![0.05, 0.2, 0.15, f64::NAN, 0.2, 1.]);
+    }
+
+    #[test]
+    fn holm_takes_cumulative_maximum() {
+        // 5 * 0.01, 4 * 0.03, max(0.12, 3 * 0.04), max(0.12, 2 * 0.04) and
+        // 0.5.
+        assert_close(holm(p().view()), array![0.05, 0.12, 0.12, f64::NAN, 0.12, 0.5]);
+        assert_close(holm(array![0.3, 0.6].view()), array![0.6, 0.6]);
+        assert_close(holm(array![0.6, 0.7].view()), array![1., 1.]);
+    }
+
+    #[test]
+    fn benjamini_hochberg_takes_cumulative_minimum() {
+        // 0.5 and 5 * 0.04 / 4 = 0.05, below 5 * 0.04 / 3, 5 * 0.03 / 2 and
+        // 5 * 0.01.
+        assert_close(benjamini_hochberg(p().view()), array![0.05, 0.05, 0.05, f64::NAN, 0.05, 0.5]);
+        assert_close(benjamini_hochberg(array![0.01, 0.02, 0.9].view()), array![0.03, 0.03, 0.9]);
+    }
+
+    #[test]
+    fn benjamini_yekutieli_scales_by_harmonic_number_and_caps_at_one() {
+        let c = 1. + 1. / 2. + 1. / 3. + 1. / 4. + 1. / 5.;
+        let q = 0.05 * c;
+        assert_close(benjamini_yekutieli(p().view()), array![q, q, q, f64::NAN, q, 1.]);
+    }
+
+    #[test]
+    fn storey_scales_by_null_proportion() {
+        // No p-value is above 0.5, but one is always counted as null, so
+        // pi0 = 1 / (5 * 0.5).
+        assert_close(storey(p().view(), 0.5), array![0.02, 0.02, 0.02, f64::NAN, 0.02, 0.2]);
+        // One p-value is above 0.2, so pi0 = 1 / (5 * 0.8).
+        assert_close(storey(p().view(), 0.2), array![0.0125, 0.0125, 0.0125, f64::NAN, 0.0125, 0.125]);
+        // Every p-value is above 0, so pi0 = 1 and q-values are BH.
+        assert_close(storey(p().view(), 0.), benjamini_hochberg(p().view()));
+    }
+
+    #[test]
+    #[should_panic(expected = "Storey lambda")]
+    fn storey_rejects_lambda_of_one() {
+        storey(p().view(), 1.);
+    }
+}
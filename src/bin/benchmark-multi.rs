@@ -9,7 +9,7 @@
 extern crate blas_src;
 extern crate lapack_src;
 
-use swe_mockup::{MockData, MockParams};
+use swe_mockup::{Error, MockData, MockParams};
 
 use ndarray::{s, Array, Axis, NewAxis};
 use rayon::iter::{IndexedParallelIterator, IntoParallelIterator, ParallelIterator};
@@ -18,7 +18,7 @@ use std::fs::File;
 use std::io::Write; // for flushing stdout
 
 #[allow(non_upper_case_globals)]
-fn main() -> Result<(), Box<dyn std::error::Error>> {
+fn main() -> Result<(), Error> {
     println!("Benchmark of multiple, parallel SwE computations.");
 
     // Try to load mock data from file, otherwise generate it on the fly.
@@ -31,7 +31,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         println!("Consider running mock-npz to generate data.");
         print!("Generating mock data on the fly...");
         std::io::stdout().flush().unwrap();
-        MockData::from_params(MockParams::default())
+        MockData::try_from_params(MockParams::default())?
     };
     println!(" done.");
     print!("{}", mock_data);
@@ -44,12 +44,13 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     let n_feat = mock_data.n_feat().get();
     let n_pred = mock_data.n_pred().get();
     let MockData {
-        n_blocks,
         block_ids,
         resid,
-        x_pinv
+        x_pinv,
+        ..
     } = mock_data;
-    let n_blocks = n_blocks.get();
+    let n_blocks = block_ids.n_blocks().get();
+    let block_ids = block_ids.into_array();
 
     // Spin up a thread pool. //
     let ncpus = std::thread::available_parallelism()?.get();
@@ -78,7 +79,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         // There is no performance benefit for doing this part in parallel.
         let blocks = std::ops::Range {
             start: 0,
-            end: n_blocks + 1, // Rust ranges exclude the last element
+            end: n_blocks, // Block ids are 0..n_blocks
         };
         for block in blocks {
             // Find indices for observations in this block.
@@ -0,0 +1,77 @@
+//! Benchmark file size versus save and load time of npz compression levels.
+//!
+//! Each level writes the mock data to a temporary file, mock-data-benchmark.npz,
+//! in the current directory and reads it back. The file is removed when the
+//! benchmark is done.
+
+// Force linking against blas and lapack backends.
+extern crate blas_src;
+extern crate lapack_src;
+
+use swe_mockup::npz::Compression;
+use swe_mockup::{Error, MockData, MockParams};
+
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Write}; // for flushing stdout
+
+fn main() -> Result<(), Error> {
+    println!("Benchmark of npz compression levels.");
+
+    // Try to load mock data from file, otherwise generate it on the fly.
+    let mock_data = if let Ok(file) = File::open("mock-data.npz") {
+        print!("Reading mock data from mock-data.npz...");
+        std::io::stdout().flush().unwrap();
+        MockData::<f64>::from_npz_file(file)?
+    } else {
+        println!("File mock-data.npz not found.");
+        println!("Consider running mock-npz to generate data.");
+        print!("Generating mock data on the fly...");
+        std::io::stdout().flush().unwrap();
+        MockData::try_from_params(MockParams::default())?
+    };
+    println!(" done.");
+    print!("{}", mock_data);
+
+    // Save and load the data at each compression level.
+    let path = "mock-data-benchmark.npz";
+    let levels = [
+        Compression::Stored,
+        Compression::Deflate { level: 1 },
+        Compression::Deflate { level: 6 },
+        Compression::Deflate { level: 9 },
+    ];
+    println!("{:>34} {:>12} {:>12} {:>12}", "compression", "size (MiB)", "save", "load");
+    for compression in levels {
+        // Time saving the data.
+        let time = std::time::Instant::now();
+        let file = File::create(path).map_err(|err| Error::io(path, err))?;
+        mock_data.save_npz_file_with(BufWriter::new(file), compression)?;
+        let time_save = time.elapsed();
+
+        // Size of the file.
+        let size = std::fs::metadata(path).map_err(|err| Error::io(path, err))?.len();
+
+        // Time loading the data.
+        let time = std::time::Instant::now();
+        let file = File::open(path).map_err(|err| Error::io(path, err))?;
+        let loaded = MockData::<f64>::from_npz_file(BufReader::new(file))?;
+        let time_load = time.elapsed();
+
+        // Make sure the optimizer sees we're using the loaded data.
+        assert_eq!(loaded.resid[[0, 0]], mock_data.resid[[0, 0]]);
+
+        println!(
+            "{:>34} {:>12.1} {:>12.2?} {:>12.2?}",
+            compression.to_string(),
+            size as f64 / (1024. * 1024.),
+            time_save,
+            time_load
+        );
+    }
+
+    // Clean up the temporary file.
+    std::fs::remove_file(path).map_err(|err| Error::io(path, err))?;
+
+    // All done, return success.
+    Ok(())
+}
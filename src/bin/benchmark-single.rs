@@ -10,7 +10,7 @@
 extern crate blas_src;
 extern crate lapack_src;
 
-use swe_mockup::{MockData, MockParams};
+use swe_mockup::{Error, MockData, MockParams};
 
 use ndarray::{s, Array, Axis, Dimension, NewAxis, ShapeBuilder};
 use num_traits::Zero;
@@ -91,7 +91,7 @@ where
 }
 
 #[allow(non_upper_case_globals)]
-fn main() -> Result<(), Box<dyn std::error::Error>> {
+fn main() -> Result<(), Error> {
     println!("Benchmark of single SwE computation.");
 
     // Try to load mock data from file, otherwise generate it on the fly.
@@ -104,7 +104,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         println!("Consider running mock-npz to generate data.");
         print!("Generating mock data on the fly...");
         std::io::stdout().flush().unwrap();
-        MockData::from_params(MockParams::default())
+        MockData::try_from_params(MockParams::default())?
     };
     println!(" done.");
     print!("{}", mock_data);
@@ -117,12 +117,13 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     let n_feat = mock_data.n_feat().get();
     let n_pred = mock_data.n_pred().get();
     let MockData {
-        n_blocks,
         block_ids,
         resid,
-        x_pinv
+        x_pinv,
+        ..
     } = mock_data;
-    let n_blocks = n_blocks.get();
+    let n_blocks = block_ids.n_blocks().get();
+    let block_ids = block_ids.into_array();
 
     // Spin up a thread pool. //
 
@@ -172,7 +173,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
             // Iterate over blocks.
             std::ops::Range {
                 start: 0,
-                end: n_blocks + 1, // Rust ranges exclude the last element
+                end: n_blocks, // Block ids are 0..n_blocks
             }
             .into_par_iter()
             .for_each(|block_id| {
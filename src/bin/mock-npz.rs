@@ -1,26 +1,137 @@
 //! Write mock data to a series of npy files in the current directory.
+//!
+//! Optionally pass a random seed as an argument to generate the same data on
+//! every run, e.g. `cargo run --release --bin mock-npz -- 42`. Pass
+//! `--compress` to deflate the arrays, optionally with a level from 0
+//! (fastest) to 9 (smallest), e.g. `--compress=9`. The default level is 6.
+//!
+//! Pass `--npy-dir` to write a directory, mock-data, with one npy file per
+//! array instead, which MATLAB's `readNPY()` can load directly, or `--mat` to
+//! write a MATLAB MAT-file, mock-data.mat, which MATLAB can `load` without any
+//! extra tooling. Neither is compressed, so they cannot be combined with
+//! `--compress`. A MAT-file holds at most 2 GB per array, which the
+//! residuals of the default parameters exceed, so `--mat` is refused before
+//! generating data that would not fit.
+//!
+//! Pass `--hdf5` to write an HDF5 file, mock-data.h5, with the same
+//! compression options as the npz file. Only one of `--npy-dir`, `--mat` and
+//! `--hdf5` can be given. This needs the binary to be built
+//! with the `hdf5` feature, e.g. `cargo run --release --features hdf5 --bin
+//! mock-npz -- --hdf5`.
+//!
+//! Pass `--keep-model` to also write the design matrix, `x`, the true
+//! coefficients, `beta`, and the responses, `y`, which is as large as the
+//! residuals.
 
 use std::fs::File;
 use std::io::Write;
-use swe_mockup::{MockData, MockParams};
+use swe_mockup::npz::Compression;
+use swe_mockup::{Error, MockData, MockParams};
+
+fn main() -> Result<(), Error> {
+    // Parse the command line arguments.
+    let mut seed = None;
+    let mut compression = Compression::Stored;
+    let mut npy_dir = false;
+    let mut mat = false;
+    let mut hdf5 = false;
+    let mut keep_model = false;
+    for arg in std::env::args().skip(1) {
+        if arg == "--keep-model" {
+            keep_model = true;
+        } else if arg == "--npy-dir" {
+            npy_dir = true;
+        } else if arg == "--mat" {
+            mat = true;
+        } else if arg == "--hdf5" {
+            if cfg!(not(feature = "hdf5")) {
+                return Err(Error::Argument("--hdf5 needs mock-npz to be built with the hdf5 feature.".to_string()));
+            }
+            hdf5 = true;
+        } else if let Some(level) = arg.strip_prefix("--compress") {
+            let level = match level.strip_prefix('=') {
+                Some(level) => level.parse().ok(),
+                None if level.is_empty() => Some(6),
+                None => None,
+            };
+            compression = level
+                .map(|level| Compression::Deflate { level })
+                .filter(Compression::is_valid)
+                .ok_or_else(|| Error::Argument(format!("Compression level must be from 0 to 9 in {}.", arg)))?;
+        } else {
+            seed = Some(arg.parse().map_err(|_| Error::Argument(format!("Seed must be an integer, not {}.", arg)))?);
+        }
+    }
+
+    // Only one output format can be written, and only npz and HDF5 files are
+    // compressed.
+    let outputs: Vec<&str> = [("--npy-dir", npy_dir), ("--mat", mat), ("--hdf5", hdf5)]
+        .iter()
+        .filter(|&&(_, flag)| flag)
+        .map(|&(name, _)| name)
+        .collect();
+    if outputs.len() > 1 {
+        return Err(Error::Argument(format!("Output options {} cannot be combined.", outputs.join(" and "))));
+    }
+    if compression != Compression::Stored && (npy_dir || mat) {
+        return Err(Error::Argument(format!("--compress cannot be combined with {}.", outputs[0])));
+    }
 
-fn main() -> Result<(), Box<dyn std::error::Error>> {
     // Mock data parmeters.
-    let params = MockParams::default();
+    let mut params = MockParams {
+        seed,
+        ..MockParams::default()
+    };
+    params.design.keep_model = keep_model;
     print!("{}", params);
 
+    // A MAT-file cannot hold the residuals, the largest array, if they are
+    // too large, so refuse before spending time generating them.
+    let resid_bytes = params
+        .n_obs
+        .get()
+        .checked_mul(params.features.n_feat().get())
+        .and_then(|n| n.checked_mul(std::mem::size_of::<f64>()));
+    if mat && resid_bytes.is_none_or(|n_bytes| n_bytes > swe_mockup::mat::MAX_BYTES) {
+        return Err(Error::Argument(
+            "The residuals are too large for a MAT-file, which holds at most 2 GB per array. Use --npy-dir or --hdf5 instead.".to_string(),
+        ));
+    }
+
     // Generate mock data.
     print!("Generating mock data...");
     std::io::stdout().flush().unwrap();
-    let mock_data = MockData::<f64>::from_params(params);
+    let mock_data = MockData::<f64>::try_from_params(params)?;
     println!(" done.");
-    println!("Generated {} blocks.", mock_data.n_blocks);
+    println!("Generated {} blocks.", mock_data.block_ids.n_blocks());
 
-    // Write data to npz file.
-    print!("Writing mock data to mock-data.npz...");
-    std::io::stdout().flush().unwrap();
-    mock_data.save_npz_file(File::create("mock-data.npz")?)?;
+    // Write data to a directory of npy files, a MAT-file, an HDF5 file or an
+    // npz file.
+    if npy_dir {
+        print!("Writing mock data to mock-data/*.npy...");
+        std::io::stdout().flush().unwrap();
+        mock_data.save_npy_dir("mock-data")?;
+    } else if mat {
+        print!("Writing mock data to mock-data.mat...");
+        std::io::stdout().flush().unwrap();
+        let file = File::create("mock-data.mat").map_err(|err| Error::io("mock-data.mat", err))?;
+        // Don't leave a truncated file behind.
+        if let Err(err) = mock_data.save_mat_file(file) {
+            let _ = std::fs::remove_file("mock-data.mat");
+            return Err(err);
+        }
+    } else if hdf5 {
+        print!("Writing mock data to mock-data.h5 with {}...", compression);
+        std::io::stdout().flush().unwrap();
+        #[cfg(feature = "hdf5")]
+        mock_data.save_hdf5_file("mock-data.h5", compression)?;
+    } else {
+        print!("Writing mock data to mock-data.npz with {}...", compression);
+        std::io::stdout().flush().unwrap();
+        let file = File::create("mock-data.npz").map_err(|err| Error::io("mock-data.npz", err))?;
+        mock_data.save_npz_file_with(file, compression)?;
+    }
     println!(" done.");
 
     Ok(())
-}
\ No newline at end of file
+}
@@ -0,0 +1,282 @@
+//! Validated block ids.
+//!
+//! The SwE sums over blocks, so the block ids must be unambiguous. A
+//! [`BlockIds`] guarantees that the ids are dense integers from zero up to but
+//! excluding the number of blocks, and that every block has at least one
+//! observation. Loops over `0..n_blocks` therefore visit every block exactly
+//! once.
+//!
+//! Real cluster identifiers are subject ids or site codes rather than dense
+//! integers. [`BlockIds::from_labels()`] maps arbitrary labels to dense ids and
+//! keeps the reverse mapping in [`BlockLabels`], so that per-block results
+//! can be reported under the original labels.
+//!
+//! Example, relabeling arbitrary labels:
+//! ```
+//! use swe_mockup::blocks::BlockIds;
+//! let (block_ids, labels) = BlockIds::from_labels(&["NDAR_B", "NDAR_A", "NDAR_B", "NDAR_C"])?;
+//! assert_eq!(block_ids.n_blocks().get(), 3);
+//! assert_eq!(block_ids.view().to_vec(), vec![0, 1, 0, 2]);
+//! assert_eq!(labels.label(1), Some(&"NDAR_A"));
+//! assert_eq!(labels.id(&"NDAR_C"), Some(2));
+//! # Ok::<(), swe_mockup::blocks::BlockIdsError>(())
+//! ```
+
+use ndarray::{Array, ArrayView, Dim};
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::num::NonZeroUsize;
+
+/// Error constructing [`BlockIds`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BlockIdsError {
+    /// There are no observations.
+    Empty,
+    /// A block id is outside the range allowed by the number of blocks.
+    OutOfRange {
+        /// The offending block id
+        block_id: usize,
+        /// Number of blocks
+        n_blocks: usize,
+    },
+    /// A block id within the range has no observations.
+    EmptyBlock {
+        /// Id of the empty block
+        block_id: usize,
+    },
+    /// A block id is too large to be dense, as there are fewer observations
+    /// than the id.
+    TooLarge {
+        /// The offending block id
+        block_id: usize,
+        /// Number of observations
+        n_obs: usize,
+    },
+    /// There are more complete blocks than observations in the old layout.
+    TooManyBlocks {
+        /// Number of complete blocks
+        n_blocks: usize,
+        /// Number of observations
+        n_obs: usize,
+    },
+}
+impl std::fmt::Display for BlockIdsError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            BlockIdsError::Empty => write!(f, "Block ids must have at least one observation."),
+            BlockIdsError::OutOfRange { block_id, n_blocks } => {
+                write!(f, "Block id {} is out of range for {} blocks.", block_id, n_blocks)
+            }
+            BlockIdsError::EmptyBlock { block_id } => write!(f, "Block {} has no observations.", block_id),
+            BlockIdsError::TooLarge { block_id, n_obs } => {
+                write!(f, "Block id {} is too large to be dense for {} observations.", block_id, n_obs)
+            }
+            BlockIdsError::TooManyBlocks { n_blocks, n_obs } => {
+                write!(f, "Cannot have {} non-empty blocks with {} observations.", n_blocks, n_obs)
+            }
+        }
+    }
+}
+impl std::error::Error for BlockIdsError {}
+
+/// Block id of each observation.
+///
+/// Ids are dense integers ranging from zero up to but excluding
+/// [`n_blocks()`](BlockIds::n_blocks), and every block has at least one
+/// observation.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BlockIds {
+    // Block id of each observation
+    ids: Array<usize, Dim<[usize; 1]>>,
+    // Number of blocks
+    n_blocks: NonZeroUsize,
+}
+impl BlockIds {
+    /// Make new `BlockIds` from ids which must already be dense. The number
+    /// of blocks is one more than the largest id. Returns an error if there
+    /// are no observations, any id is not less than the number of
+    /// observations, or any id below the largest has no observations.
+    pub fn new(ids: Array<usize, Dim<[usize; 1]>>) -> Result<Self, BlockIdsError> {
+        // Dense ids are less than the number of observations, which bounds the
+        // flags allocated below.
+        let n_obs = ids.len();
+        if let Some(&block_id) = ids.iter().find(|&&block_id| block_id >= n_obs) {
+            return Err(BlockIdsError::TooLarge { block_id, n_obs });
+        }
+        let n_blocks = ids.iter().max().ok_or(BlockIdsError::Empty)? + 1;
+        // Every block must have at least one observation.
+        let mut non_empty = vec![false; n_blocks];
+        for &block_id in ids.iter() {
+            non_empty[block_id] = true;
+        }
+        if let Some(block_id) = non_empty.iter().position(|&non_empty| !non_empty) {
+            return Err(BlockIdsError::EmptyBlock { block_id });
+        }
+        Ok(Self {
+            ids,
+            n_blocks: NonZeroUsize::new(n_blocks).unwrap(),
+        })
+    }
+
+    /// Make new `BlockIds` from the label of each observation, e.g. family or
+    /// subject ids from real data. Distinct labels are assigned dense ids in
+    /// order of first appearance. Returns the block ids together with the
+    /// mapping between ids and labels. Returns an error if there are no
+    /// labels.
+    pub fn from_labels<T: Hash + Eq + Clone>(labels: &[T]) -> Result<(Self, BlockLabels<T>), BlockIdsError> {
+        // Assign ids in order of first appearance.
+        let mut block_labels = BlockLabels {
+            labels: Vec::new(),
+            ids: HashMap::new(),
+        };
+        let ids = labels
+            .iter()
+            .map(|label| {
+                *block_labels.ids.entry(label.clone()).or_insert_with(|| {
+                    block_labels.labels.push(label.clone());
+                    block_labels.labels.len() - 1
+                })
+            })
+            .collect();
+        Ok((Self::new(ids)?, block_labels))
+    }
+
+    /// Make new `BlockIds` from the layout of block ids in npz files written
+    /// by earlier versions of this crate. In that layout ids of complete
+    /// blocks start at one and go up to and including `n_blocks`, and a
+    /// trailing partial block, which may be empty, has id zero. The complete
+    /// blocks keep their order with ids starting at zero, and the partial
+    /// block, if not empty, becomes the last block. Returns an error if there
+    /// are more complete blocks than observations, any id is greater than
+    /// `n_blocks` or any complete block has no observations.
+    pub fn from_old_layout(ids: Array<usize, Dim<[usize; 1]>>, n_blocks: usize) -> Result<Self, BlockIdsError> {
+        // Every complete block must have at least one observation, but the
+        // partial block is allowed to be empty.
+        let n_obs = ids.len();
+        let len = match n_blocks.checked_add(1) {
+            Some(len) if n_blocks <= n_obs => len,
+            _ => return Err(BlockIdsError::TooManyBlocks { n_blocks, n_obs }),
+        };
+        let mut non_empty = vec![false; len];
+        for &block_id in ids.iter() {
+            if block_id > n_blocks {
+                return Err(BlockIdsError::OutOfRange { block_id, n_blocks });
+            }
+            non_empty[block_id] = true;
+        }
+        if let Some(block_id) = non_empty.iter().skip(1).position(|&non_empty| !non_empty) {
+            return Err(BlockIdsError::EmptyBlock { block_id: block_id + 1 });
+        }
+        // Move the partial block to the end and shift the others down.
+        Self::new(ids.mapv_into(|block_id| if block_id == 0 { n_blocks } else { block_id - 1 }))
+    }
+
+    /// Number of blocks.
+    pub fn n_blocks(&self) -> NonZeroUsize {
+        self.n_blocks
+    }
+
+    /// Number of observations.
+    pub fn n_obs(&self) -> NonZeroUsize {
+        NonZeroUsize::new(self.ids.len()).unwrap()
+    }
+
+    /// View of the block id of each observation.
+    pub fn view(&self) -> ArrayView<'_, usize, Dim<[usize; 1]>> {
+        self.ids.view()
+    }
+
+    /// Unwrap the block id of each observation.
+    pub fn into_array(self) -> Array<usize, Dim<[usize; 1]>> {
+        self.ids
+    }
+}
+
+/// Mapping between dense block ids and the original labels of the blocks.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BlockLabels<T: Hash + Eq> {
+    // Label of each block id
+    labels: Vec<T>,
+    // Block id of each label
+    ids: HashMap<T, usize>,
+}
+impl<T: Hash + Eq + Clone> BlockLabels<T> {
+    /// Make new `BlockLabels` where `labels[i]` is the label of block id `i`.
+    /// Returns None if there are no labels or any label is repeated.
+    pub fn new(labels: Vec<T>) -> Option<Self> {
+        let mut ids = HashMap::with_capacity(labels.len());
+        for (block_id, label) in labels.iter().enumerate() {
+            if ids.insert(label.clone(), block_id).is_some() {
+                return None;
+            }
+        }
+        if labels.is_empty() {
+            None
+        } else {
+            Some(Self { labels, ids })
+        }
+    }
+
+    /// Label of a block id, or None if the id is out of range.
+    pub fn label(&self, block_id: usize) -> Option<&T> {
+        self.labels.get(block_id)
+    }
+
+    /// Block id of a label, or None if there is no block with the label.
+    pub fn id(&self, label: &T) -> Option<usize> {
+        self.ids.get(label).copied()
+    }
+
+    /// Labels of all the blocks in order of block id.
+    pub fn labels(&self) -> &[T] {
+        &self.labels
+    }
+
+    /// Number of blocks.
+    pub fn n_blocks(&self) -> NonZeroUsize {
+        NonZeroUsize::new(self.labels.len()).unwrap()
+    }
+
+    /// Convert the labels to strings, e.g. to save them to a file.
+    pub fn to_strings(&self) -> BlockLabels<String>
+    where
+        T: std::fmt::Display,
+    {
+        // Distinct labels may have the same string representation, but only
+        // for unusual implementations of Display.
+        BlockLabels::new(self.labels.iter().map(|label| label.to_string()).collect())
+            .expect("Distinct labels must have distinct strings.")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn new_rejects_ids_too_large_to_be_dense() {
+        let err = BlockIds::new(Array::from(vec![0, usize::MAX])).unwrap_err();
+        assert_eq!(err, BlockIdsError::TooLarge { block_id: usize::MAX, n_obs: 2 });
+        let err = BlockIds::new(Array::from(vec![0, 1, 3])).unwrap_err();
+        assert_eq!(err, BlockIdsError::TooLarge { block_id: 3, n_obs: 3 });
+        assert_eq!(BlockIds::new(Array::from(vec![0, 2, 2])).unwrap_err(), BlockIdsError::EmptyBlock { block_id: 1 });
+        assert_eq!(BlockIds::new(Array::from(vec![])).unwrap_err(), BlockIdsError::Empty);
+    }
+
+    #[test]
+    fn from_old_layout_rejects_too_many_blocks() {
+        let err = BlockIds::from_old_layout(Array::from(vec![1, 2]), usize::MAX).unwrap_err();
+        assert_eq!(err, BlockIdsError::TooManyBlocks { n_blocks: usize::MAX, n_obs: 2 });
+        let err = BlockIds::from_old_layout(Array::from(vec![1, 2, 3]), 2).unwrap_err();
+        assert_eq!(err, BlockIdsError::OutOfRange { block_id: 3, n_blocks: 2 });
+    }
+
+    #[test]
+    fn from_old_layout_moves_partial_block_last() {
+        let block_ids = BlockIds::from_old_layout(Array::from(vec![1, 1, 2, 0]), 2).unwrap();
+        assert_eq!(block_ids.n_blocks().get(), 3);
+        assert_eq!(block_ids.view().to_vec(), vec![0, 0, 1, 2]);
+        let block_ids = BlockIds::from_old_layout(Array::from(vec![1, 2, 2]), 2).unwrap();
+        assert_eq!(block_ids.n_blocks().get(), 2);
+    }
+}
@@ -0,0 +1,323 @@
+//! Checkpointing long-running resampling tests.
+//!
+//! A permutation test with 10,000 replicates over 55k features can run for
+//! hours and may be pre-empted. Every `interval` replicates the complete
+//! state of the test (random number generator, number of completed
+//! replicates, max-statistic distribution and exceedance counters) is written
+//! to an npz file. Resuming from the file continues the test exactly as if it
+//! had never been interrupted.
+//!
+//! Example:
+//! ```no_run
+//! use std::fs::File;
+//! use std::num::NonZeroUsize;
+//! # use swe_mockup::{MockData, MockParams};
+//! # use swe_mockup::exchange::Shuffle;
+//! # use swe_mockup::maxstat::Tail;
+//! # use swe_mockup::permute::{PermutationScheme, PermutationTest};
+//! # use swe_mockup::swe;
+//! use swe_mockup::checkpoint::Checkpoint;
+//! # let mock_data = MockData::<f64>::from_params(MockParams::default());
+//! # let blocks = swe::block_indices(mock_data.block_ids.view());
+//! # let stat = |y: ndarray::ArrayView2<f64>| swe::t_stat(mock_data.x_pinv.view(), y, &blocks, 0);
+//! let checkpoint = Checkpoint {
+//!     path: "permutation-checkpoint.npz".into(),
+//!     interval: NonZeroUsize::new(100).unwrap(),
+//! };
+//! let tree = mock_data.exchangeability_tree(Shuffle::WholeBlock);
+//! let mut test = if checkpoint.exists() {
+//!     PermutationTest::from_checkpoint(File::open(&checkpoint.path)?, tree)?
+//! } else {
+//!     let observed = stat(mock_data.resid.view());
+//!     PermutationTest::new(tree, PermutationScheme::default(), observed, Tail::Both, 42)?
+//! };
+//! test.run_checkpointed(10000, mock_data.resid.view(), stat, &checkpoint)?;
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+//!
+//! Other resampling loops, e.g. a bootstrap, checkpoint their random number
+//! generator and max-statistic distribution with [`ResamplingState`].
+//!
+//! Example, a bootstrap of the column means:
+//! ```no_run
+//! use std::fs::File;
+//! use std::num::NonZeroUsize;
+//! use ndarray::Axis;
+//! use rand::Rng;
+//! # use swe_mockup::{MockData, MockParams};
+//! use swe_mockup::checkpoint::{Checkpoint, ResamplingState};
+//! use swe_mockup::maxstat::{MaxStat, Tail};
+//! # let mock_data = MockData::<f64>::from_params(MockParams::default());
+//! let data = mock_data.resid.view();
+//! let checkpoint = Checkpoint {
+//!     path: "bootstrap-checkpoint.npz".into(),
+//!     interval: NonZeroUsize::new(100).unwrap(),
+//! };
+//! let mut state = if checkpoint.exists() {
+//!     ResamplingState::from_checkpoint(File::open(&checkpoint.path)?)?
+//! } else {
+//!     ResamplingState::new(MaxStat::new(data.mean_axis(Axis(0)).unwrap(), Tail::Both), 42)
+//! };
+//! while state.max_stat.n_replicates() < 1000 {
+//!     let rows: Vec<usize> = (0..data.nrows()).map(|_| state.rng.gen_range(0..data.nrows())).collect();
+//!     let means = data.select(Axis(0), &rows).mean_axis(Axis(0)).unwrap();
+//!     state.max_stat.add_replicate(means.view());
+//!     if state.max_stat.n_replicates() % checkpoint.interval.get() == 0 {
+//!         state.write_checkpoint(&checkpoint)?;
+//!     }
+//! }
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+
+use crate::maxstat::MaxStat;
+use ndarray::{Array, Dim};
+use ndarray_npy::{NpzReader, NpzWriter, ReadNpzError, ReadableElement, WritableElement, WriteNpzError};
+use num_traits::Float;
+use rand::SeedableRng;
+use rand_chacha::ChaCha8Rng;
+use std::fs::File;
+use std::io::{BufWriter, Read, Seek, Write};
+use std::num::NonZeroUsize;
+use std::path::{Path, PathBuf};
+
+/// Where and how often to write checkpoints.
+#[derive(Clone, Debug)]
+pub struct Checkpoint {
+    /// Path of the checkpoint file
+    pub path: PathBuf,
+    /// Number of replicates between checkpoints
+    pub interval: NonZeroUsize,
+}
+impl Checkpoint {
+    /// Whether a checkpoint file exists to resume from.
+    pub fn exists(&self) -> bool {
+        self.path.is_file()
+    }
+
+    // Write a checkpoint file with `write`. The checkpoint is first written
+    // to a temporary file which then replaces the checkpoint file, so that an
+    // interruption while writing never leaves a corrupt checkpoint behind.
+    // The temporary file is synced to disk before the rename, and the
+    // directory after it, so that a crash, not just an interruption, leaves
+    // either the old or the new checkpoint.
+    pub(crate) fn write<F>(&self, write: F) -> Result<(), CheckpointError>
+    where
+        F: FnOnce(&mut BufWriter<File>) -> Result<(), WriteNpzError>,
+    {
+        let mut tmp_path = self.path.clone().into_os_string();
+        tmp_path.push(".tmp");
+        let tmp_path = Path::new(&tmp_path);
+        let mut w = BufWriter::new(File::create(tmp_path)?);
+        write(&mut w)?;
+        let file = w.into_inner().map_err(|err| err.into_error())?;
+        file.sync_all()?;
+        drop(file);
+        std::fs::rename(tmp_path, &self.path)?;
+        // Directories can only be opened as files on Unix.
+        #[cfg(unix)]
+        {
+            let dir = match self.path.parent() {
+                Some(dir) if !dir.as_os_str().is_empty() => dir,
+                _ => Path::new("."),
+            };
+            File::open(dir)?.sync_all()?;
+        }
+        Ok(())
+    }
+}
+impl std::fmt::Display for Checkpoint {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Checkpoint to {} every {} replicates", self.path.display(), self.interval)
+    }
+}
+
+/// State of a resampling loop, such as a bootstrap, which draws replicates
+/// with a random number generator and accumulates their max-statistic
+/// distribution. Resuming from a checkpoint continues with exactly the random
+/// numbers the loop would have drawn had it not been interrupted.
+#[derive(Clone, Debug)]
+pub struct ResamplingState<S> {
+    /// Random number generator to draw the replicates with
+    pub rng: ChaCha8Rng,
+    /// Max-statistic distribution of the replicates so far
+    pub max_stat: MaxStat<S>,
+}
+impl<S> ResamplingState<S> {
+    /// Start a new loop with an empty max-statistic distribution and a random
+    /// number generator initialized by `seed`.
+    pub fn new(max_stat: MaxStat<S>, seed: u64) -> Self {
+        Self {
+            rng: ChaCha8Rng::seed_from_u64(seed),
+            max_stat,
+        }
+    }
+}
+impl<S> ResamplingState<S>
+where
+    S: Float + WritableElement,
+{
+    /// Save the complete state to an npz file, to resume from with
+    /// [`ResamplingState::from_checkpoint()`].
+    pub fn save_checkpoint<W: Seek + Write>(&self, w: W) -> Result<(), WriteNpzError> {
+        let mut npz = NpzWriter::new(w);
+        write_rng(&mut npz, &self.rng)?;
+        self.max_stat.write_npz(&mut npz)?;
+        npz.finish()?;
+        Ok(())
+    }
+
+    /// Save the complete state to the checkpoint file, replacing it without
+    /// ever leaving a corrupt checkpoint behind.
+    pub fn write_checkpoint(&self, checkpoint: &Checkpoint) -> Result<(), CheckpointError> {
+        checkpoint.write(|w| self.save_checkpoint(w))
+    }
+}
+impl<S> ResamplingState<S>
+where
+    S: Float + ReadableElement,
+{
+    /// Resume from a checkpoint saved by
+    /// [`ResamplingState::save_checkpoint()`].
+    pub fn from_checkpoint<R: Seek + Read>(r: R) -> Result<Self, CheckpointError> {
+        let mut npz = NpzReader::new(r)?;
+        let rng = read_rng(&mut npz)?;
+        let max_stat = MaxStat::read_npz(&mut npz)?;
+        Ok(Self { rng, max_stat })
+    }
+}
+
+/// Error writing or resuming from a checkpoint.
+#[derive(Debug)]
+pub enum CheckpointError {
+    /// Error creating or renaming the checkpoint file.
+    Io(std::io::Error),
+    /// Error reading an array from the checkpoint file.
+    Read(ReadNpzError),
+    /// Error writing an array to the checkpoint file.
+    Write(WriteNpzError),
+    /// The checkpoint file was read but its contents are invalid or do not
+    /// match the test being resumed.
+    Invalid(String),
+}
+impl std::fmt::Display for CheckpointError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            CheckpointError::Io(err) => write!(f, "Error accessing checkpoint file: {}", err),
+            CheckpointError::Read(err) => write!(f, "Error reading checkpoint: {}", err),
+            CheckpointError::Write(err) => write!(f, "Error writing checkpoint: {}", err),
+            CheckpointError::Invalid(msg) => write!(f, "Invalid checkpoint: {}", msg),
+        }
+    }
+}
+impl std::error::Error for CheckpointError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            CheckpointError::Io(err) => Some(err),
+            CheckpointError::Read(err) => Some(err),
+            CheckpointError::Write(err) => Some(err),
+            CheckpointError::Invalid(_) => None,
+        }
+    }
+}
+impl From<std::io::Error> for CheckpointError {
+    fn from(err: std::io::Error) -> Self {
+        CheckpointError::Io(err)
+    }
+}
+impl From<ReadNpzError> for CheckpointError {
+    fn from(err: ReadNpzError) -> Self {
+        CheckpointError::Read(err)
+    }
+}
+impl From<WriteNpzError> for CheckpointError {
+    fn from(err: WriteNpzError) -> Self {
+        CheckpointError::Write(err)
+    }
+}
+
+// Add the state of the random number generator to an npz file: the 32-byte
+// seed, and the stream and 128-bit word position as three 64-bit words.
+pub(crate) fn write_rng<W: Seek + Write>(npz: &mut NpzWriter<W>, rng: &ChaCha8Rng) -> Result<(), WriteNpzError> {
+    let word_pos = rng.get_word_pos();
+    let position = Array::from(vec![
+        rng.get_stream(),
+        (word_pos >> 64) as u64,
+        word_pos as u64,
+    ]);
+    npz.add_array("rng_seed", &Array::from(rng.get_seed().to_vec()))?;
+    npz.add_array("rng_position", &position)?;
+    Ok(())
+}
+
+// Restore a random number generator written by `write_rng()`.
+pub(crate) fn read_rng<R: Seek + Read>(npz: &mut NpzReader<R>) -> Result<ChaCha8Rng, CheckpointError> {
+    let seed: Array<u8, Dim<[usize; 1]>> = npz.by_name("rng_seed")?;
+    let position: Array<u64, Dim<[usize; 1]>> = npz.by_name("rng_position")?;
+    let seed: [u8; 32] = seed
+        .as_slice()
+        .and_then(|seed| seed.try_into().ok())
+        .ok_or_else(|| CheckpointError::Invalid(format!("RNG seed has {} bytes, expected 32.", seed.len())))?;
+    if position.len() != 3 {
+        return Err(CheckpointError::Invalid(format!(
+            "RNG position has {} words, expected 3.",
+            position.len()
+        )));
+    }
+    let mut rng = ChaCha8Rng::from_seed(seed);
+    rng.set_stream(position[0]);
+    rng.set_word_pos(((position[1] as u128) << 64) | position[2] as u128);
+    Ok(rng)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::maxstat::Tail;
+    use ndarray::{ArrayView, Axis};
+    use rand::Rng;
+
+    // Draw one bootstrap replicate of the column means of the data.
+    fn bootstrap(state: &mut ResamplingState<f64>, data: ArrayView<f64, Dim<[usize; 2]>>) {
+        let rows: Vec<usize> = (0..data.nrows()).map(|_| state.rng.gen_range(0..data.nrows())).collect();
+        let means = data.select(Axis(0), &rows).mean_axis(Axis(0)).unwrap();
+        state.max_stat.add_replicate(means.view());
+    }
+
+    #[test]
+    fn resumed_bootstrap_matches_uninterrupted_bootstrap() {
+        let data = Array::from_shape_fn((15, 6), |(obs, feat)| ((obs * 6 + feat) as f64).cos());
+        let new_state = || ResamplingState::new(MaxStat::new(data.mean_axis(Axis(0)).unwrap(), Tail::Both), 7);
+        let n_total = 30;
+
+        let mut straight = new_state();
+        for _ in 0..n_total {
+            bootstrap(&mut straight, data.view());
+        }
+
+        // Run a third of the replicates with checkpoints, then resume.
+        let checkpoint = Checkpoint {
+            path: std::env::temp_dir().join(format!("swe-mockup-bootstrap-{}.npz", std::process::id())),
+            interval: NonZeroUsize::new(4).unwrap(),
+        };
+        let mut first = new_state();
+        while first.max_stat.n_replicates() < n_total / 3 {
+            bootstrap(&mut first, data.view());
+            if first.max_stat.n_replicates() % checkpoint.interval.get() == 0 {
+                first.write_checkpoint(&checkpoint).unwrap();
+            }
+        }
+        drop(first);
+        let mut resumed = ResamplingState::<f64>::from_checkpoint(File::open(&checkpoint.path).unwrap()).unwrap();
+        std::fs::remove_file(&checkpoint.path).unwrap();
+        // The last checkpoint was at 8 replicates, so 2 are run again.
+        assert_eq!(resumed.max_stat.n_replicates(), 8);
+        while resumed.max_stat.n_replicates() < n_total {
+            bootstrap(&mut resumed, data.view());
+        }
+
+        assert_eq!(resumed.max_stat.max(), straight.max_stat.max());
+        assert_eq!(resumed.max_stat.min(), straight.max_stat.min());
+        assert_eq!(resumed.max_stat.p_step_down(), straight.max_stat.p_step_down());
+        assert_eq!(resumed.rng.get_word_pos(), straight.rng.get_word_pos());
+    }
+}
@@ -0,0 +1,283 @@
+//! Crate-wide error type.
+//!
+//! Each module has its own error type describing what can go wrong there.
+//! [`Error`] gathers them together with I/O, npz, HDF5, Arrow, linear algebra
+//! and thread pool errors, adding context about which file or array failed, so
+//! that binaries and downstream tools can propagate any failure with `?` and
+//! still match on what kind of failure it was.
+
+use crate::blocks::BlockIdsError;
+use crate::checkpoint::CheckpointError;
+use crate::exchange::ExchangeabilityError;
+use crate::mat::MatError;
+use crate::npz::Compression;
+use crate::permute::PermuteError;
+use crate::phenotype::PhenotypeError;
+use crate::MockDataError;
+use ndarray_linalg::error::LinalgError;
+use ndarray_npy::{ReadNpyError, ReadNpzError, WriteNpyError, WriteNpzError};
+use std::path::PathBuf;
+
+/// Any error from this crate.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum Error {
+    /// Error opening, creating or accessing a file.
+    Io {
+        /// Path of the file, if known
+        path: Option<PathBuf>,
+        /// Underlying error
+        source: std::io::Error,
+    },
+    /// Error reading an npy file.
+    ReadNpy {
+        /// Path of the file
+        path: PathBuf,
+        /// Underlying error
+        source: ReadNpyError,
+    },
+    /// Error writing an npy file.
+    WriteNpy {
+        /// Path of the file
+        path: PathBuf,
+        /// Underlying error
+        source: WriteNpyError,
+    },
+    /// Error reading an npz file.
+    ReadNpz {
+        /// Name of the array being read, if any
+        array: Option<String>,
+        /// Underlying error
+        source: ReadNpzError,
+    },
+    /// Error writing an npz file.
+    WriteNpz {
+        /// Name of the array being written, if any
+        array: Option<String>,
+        /// Underlying error
+        source: WriteNpzError,
+    },
+    /// Error writing the zip archive of an npz file.
+    Zip {
+        /// Name of the array being written, if any
+        array: Option<String>,
+        /// Underlying error
+        source: std::io::Error,
+    },
+    /// Compression is not valid, see [`Compression::is_valid()`].
+    InvalidCompression(Compression),
+    /// Error reading or writing a MAT-file.
+    Mat(MatError),
+    /// Error reading or writing an HDF5 file.
+    #[cfg(feature = "hdf5")]
+    Hdf5 {
+        /// Name of the array being read or written, if any
+        array: Option<String>,
+        /// Underlying error
+        source: hdf5::Error,
+    },
+    /// Error building or writing an Arrow table of statistics.
+    #[cfg(feature = "arrow")]
+    Arrow(arrow_schema::ArrowError),
+    /// Error writing a Parquet file of statistics.
+    #[cfg(feature = "arrow")]
+    Parquet(parquet::errors::ParquetError),
+    /// Mock data read from a file are not valid.
+    MockData(MockDataError),
+    /// Parameters for generating mock data are not valid.
+    InvalidParams(String),
+    /// Block ids are not valid.
+    BlockIds(BlockIdsError),
+    /// Phenotype table could not be read or aligned with the observations.
+    Phenotype(PhenotypeError),
+    /// Levels of exchangeability blocks are not valid.
+    Exchangeability(ExchangeabilityError),
+    /// Permutation test could not be set up.
+    Permute(PermuteError),
+    /// Error writing or resuming from a checkpoint.
+    Checkpoint(CheckpointError),
+    /// Linear algebra failure, e.g. the SVD of the design matrix did not
+    /// converge.
+    Linalg(LinalgError),
+    /// Thread pool could not be built.
+    ThreadPool(rayon::ThreadPoolBuildError),
+    /// Invalid command line argument.
+    Argument(String),
+}
+impl Error {
+    /// Attach the path of a file to an I/O error.
+    pub fn io<P: Into<PathBuf>>(path: P, source: std::io::Error) -> Self {
+        Error::Io {
+            path: Some(path.into()),
+            source,
+        }
+    }
+
+    // Attach the name of an array to an error reading an npz file.
+    pub(crate) fn read_npz(array: &str) -> impl FnOnce(ReadNpzError) -> Self + '_ {
+        move |source| Error::ReadNpz {
+            array: Some(array.to_string()),
+            source,
+        }
+    }
+
+    // Attach the name of an array to an error writing an npz file.
+    pub(crate) fn write_npz(array: &str) -> impl FnOnce(WriteNpzError) -> Self + '_ {
+        move |source| Error::WriteNpz {
+            array: Some(array.to_string()),
+            source,
+        }
+    }
+
+    // Attach the name of an array to an error reading or writing an HDF5
+    // file.
+    #[cfg(feature = "hdf5")]
+    pub(crate) fn hdf5(array: &str) -> impl FnOnce(hdf5::Error) -> Self + '_ {
+        move |source| Error::Hdf5 {
+            array: Some(array.to_string()),
+            source,
+        }
+    }
+}
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Error::Io { path: Some(path), source } => write!(f, "Error accessing {}: {}", path.display(), source),
+            Error::Io { path: None, source } => write!(f, "I/O error: {}", source),
+            Error::ReadNpy { path, source } => write!(f, "Error reading {}: {}", path.display(), source),
+            Error::WriteNpy { path, source } => write!(f, "Error writing {}: {}", path.display(), source),
+            Error::ReadNpz { array: Some(array), source } => write!(f, "Error reading array {}: {}", array, source),
+            Error::ReadNpz { array: None, source } => write!(f, "Error reading npz file: {}", source),
+            Error::WriteNpz { array: Some(array), source } => write!(f, "Error writing array {}: {}", array, source),
+            Error::WriteNpz { array: None, source } => write!(f, "Error writing npz file: {}", source),
+            Error::Zip { array: Some(array), source } => write!(f, "Error writing array {}: {}", array, source),
+            Error::Zip { array: None, source } => write!(f, "Error writing npz file: {}", source),
+            Error::InvalidCompression(compression) => write!(f, "Invalid compression: {}.", compression),
+            Error::Mat(err) => write!(f, "{}", err),
+            #[cfg(feature = "hdf5")]
+            Error::Hdf5 { array: Some(array), source } => write!(f, "Error accessing array {}: {}", array, source),
+            #[cfg(feature = "hdf5")]
+            Error::Hdf5 { array: None, source } => write!(f, "Error accessing HDF5 file: {}", source),
+            #[cfg(feature = "arrow")]
+            Error::Arrow(err) => write!(f, "Arrow error: {}", err),
+            #[cfg(feature = "arrow")]
+            Error::Parquet(err) => write!(f, "Error writing Parquet file: {}", err),
+            Error::MockData(err) => write!(f, "{}", err),
+            Error::InvalidParams(msg) => write!(f, "Invalid mock data parameters: {}", msg),
+            Error::BlockIds(err) => write!(f, "Invalid block ids: {}", err),
+            Error::Phenotype(err) => write!(f, "{}", err),
+            Error::Exchangeability(err) => write!(f, "Invalid exchangeability blocks: {}", err),
+            Error::Permute(err) => write!(f, "{}", err),
+            Error::Checkpoint(err) => write!(f, "{}", err),
+            Error::Linalg(err) => write!(f, "Linear algebra error: {}", err),
+            Error::ThreadPool(err) => write!(f, "Error building thread pool: {}", err),
+            Error::Argument(msg) => write!(f, "Invalid argument: {}", msg),
+        }
+    }
+}
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io { source, .. } => Some(source),
+            Error::ReadNpy { source, .. } => Some(source),
+            Error::WriteNpy { source, .. } => Some(source),
+            Error::ReadNpz { source, .. } => Some(source),
+            Error::WriteNpz { source, .. } => Some(source),
+            Error::Zip { source, .. } => Some(source),
+            Error::Mat(err) => Some(err),
+            #[cfg(feature = "hdf5")]
+            Error::Hdf5 { source, .. } => Some(source),
+            #[cfg(feature = "arrow")]
+            Error::Arrow(err) => Some(err),
+            #[cfg(feature = "arrow")]
+            Error::Parquet(err) => Some(err),
+            Error::MockData(err) => Some(err),
+            Error::BlockIds(err) => Some(err),
+            Error::Phenotype(err) => Some(err),
+            Error::Exchangeability(err) => Some(err),
+            Error::Permute(err) => Some(err),
+            Error::Checkpoint(err) => Some(err),
+            Error::Linalg(err) => Some(err),
+            Error::ThreadPool(err) => Some(err),
+            Error::InvalidCompression(_) | Error::InvalidParams(_) | Error::Argument(_) => None,
+        }
+    }
+}
+impl From<std::io::Error> for Error {
+    fn from(source: std::io::Error) -> Self {
+        Error::Io { path: None, source }
+    }
+}
+impl From<ReadNpzError> for Error {
+    fn from(source: ReadNpzError) -> Self {
+        Error::ReadNpz { array: None, source }
+    }
+}
+impl From<WriteNpzError> for Error {
+    fn from(source: WriteNpzError) -> Self {
+        Error::WriteNpz { array: None, source }
+    }
+}
+impl From<MatError> for Error {
+    fn from(err: MatError) -> Self {
+        Error::Mat(err)
+    }
+}
+#[cfg(feature = "hdf5")]
+impl From<hdf5::Error> for Error {
+    fn from(source: hdf5::Error) -> Self {
+        Error::Hdf5 { array: None, source }
+    }
+}
+#[cfg(feature = "arrow")]
+impl From<arrow_schema::ArrowError> for Error {
+    fn from(err: arrow_schema::ArrowError) -> Self {
+        Error::Arrow(err)
+    }
+}
+#[cfg(feature = "arrow")]
+impl From<parquet::errors::ParquetError> for Error {
+    fn from(err: parquet::errors::ParquetError) -> Self {
+        Error::Parquet(err)
+    }
+}
+impl From<MockDataError> for Error {
+    fn from(err: MockDataError) -> Self {
+        Error::MockData(err)
+    }
+}
+impl From<BlockIdsError> for Error {
+    fn from(err: BlockIdsError) -> Self {
+        Error::BlockIds(err)
+    }
+}
+impl From<PhenotypeError> for Error {
+    fn from(err: PhenotypeError) -> Self {
+        Error::Phenotype(err)
+    }
+}
+impl From<ExchangeabilityError> for Error {
+    fn from(err: ExchangeabilityError) -> Self {
+        Error::Exchangeability(err)
+    }
+}
+impl From<PermuteError> for Error {
+    fn from(err: PermuteError) -> Self {
+        Error::Permute(err)
+    }
+}
+impl From<CheckpointError> for Error {
+    fn from(err: CheckpointError) -> Self {
+        Error::Checkpoint(err)
+    }
+}
+impl From<LinalgError> for Error {
+    fn from(err: LinalgError) -> Self {
+        Error::Linalg(err)
+    }
+}
+impl From<rayon::ThreadPoolBuildError> for Error {
+    fn from(err: rayon::ThreadPoolBuildError) -> Self {
+        Error::ThreadPool(err)
+    }
+}
@@ -0,0 +1,448 @@
+//! Multi-level exchangeability blocks for permutation inference.
+//!
+//! A single vector of block ids cannot express nested structure such as
+//! families within sites. An [`ExchangeabilityTree`] is built from several
+//! levels of block ids, outermost first, each with a flag saying whether its
+//! blocks are shuffled as whole units or only internally. This follows the
+//! multi-level exchangeability blocks of PALM.
+//!
+//! Example, families nested within sites, where families of the same shape
+//! can be swapped within a site and siblings can be swapped within a family:
+//! ```
+//! use ndarray::array;
+//! use swe_mockup::exchange::{BlockLevel, ExchangeabilityTree, Shuffle};
+//! let sites = array![0, 0, 0, 0, 1, 1];
+//! let families = array![0, 0, 1, 1, 2, 2];
+//! let tree = ExchangeabilityTree::new(vec![
+//!     BlockLevel { ids: sites, shuffle: Shuffle::WithinBlock },
+//!     BlockLevel { ids: families, shuffle: Shuffle::WholeBlock },
+//! ])?;
+//! assert_eq!(tree.n_obs(), 6);
+//! tree.check_permutable()?;
+//! # Ok::<(), swe_mockup::exchange::ExchangeabilityError>(())
+//! ```
+
+use ndarray::{Array, Dim};
+use rand::seq::SliceRandom;
+use rand::Rng;
+use std::collections::{BTreeMap, HashMap};
+
+/// How the blocks at one level of an exchangeability tree are resampled.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Shuffle {
+    /// Each block is sign-flipped as a whole unit, and is permuted as a whole
+    /// unit with its sibling blocks, i.e. the other blocks at this level
+    /// within the same parent block. Whole-block permutation requires sibling
+    /// blocks to have identical structure. Observations within the block are
+    /// not resampled individually.
+    WholeBlock,
+    /// Blocks stay in place and are only resampled internally. At the
+    /// innermost level, observations are sign-flipped individually and
+    /// permuted only with other observations in the same block.
+    #[default]
+    WithinBlock,
+}
+impl std::fmt::Display for Shuffle {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Shuffle::WholeBlock => write!(f, "whole-block"),
+            Shuffle::WithinBlock => write!(f, "within-block"),
+        }
+    }
+}
+
+/// One level of an exchangeability tree.
+#[derive(Clone, Debug)]
+pub struct BlockLevel {
+    /// Block id of each observation at this level. Ids need not be
+    /// contiguous, but each block must lie entirely within one block of the
+    /// enclosing level.
+    pub ids: Array<usize, Dim<[usize; 1]>>,
+    /// How the blocks at this level are resampled.
+    pub shuffle: Shuffle,
+}
+
+/// Error constructing or validating an exchangeability tree.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ExchangeabilityError {
+    /// The tree has no levels.
+    NoLevels,
+    /// A level does not have the same number of observations as the first
+    /// level.
+    LengthMismatch {
+        /// Index of the offending level, outermost first
+        level: usize,
+        /// Number of observations in the first level
+        expected: usize,
+        /// Number of observations in the offending level
+        found: usize,
+    },
+    /// A block spans more than one block of the enclosing level.
+    NotNested {
+        /// Index of the offending level, outermost first
+        level: usize,
+        /// Id of the offending block
+        block_id: usize,
+    },
+    /// Whole-block permutation was requested at a level where sibling blocks
+    /// do not have identical structure.
+    IncompatibleBlocks {
+        /// Index of the offending level, outermost first
+        level: usize,
+    },
+}
+impl std::fmt::Display for ExchangeabilityError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            ExchangeabilityError::NoLevels => {
+                write!(f, "Exchangeability tree must have at least one level.")
+            }
+            ExchangeabilityError::LengthMismatch { level, expected, found } => write!(
+                f,
+                "Level {} of exchangeability tree has {} observations but expected {}.",
+                level, found, expected
+            ),
+            ExchangeabilityError::NotNested { level, block_id } => write!(
+                f,
+                "Block {} at level {} of exchangeability tree spans more than one enclosing block.",
+                block_id, level
+            ),
+            ExchangeabilityError::IncompatibleBlocks { level } => write!(
+                f,
+                "Whole-block permutation at level {} of exchangeability tree requires sibling blocks with identical structure.",
+                level
+            ),
+        }
+    }
+}
+impl std::error::Error for ExchangeabilityError {}
+
+// Structure of a block, ignoring which observations it contains. Two blocks
+// with the same shape can be exchanged as whole units.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum Shape {
+    // Innermost block with this many observations.
+    Observations(usize),
+    // Block containing sub-blocks of these shapes.
+    Blocks(Vec<Shape>),
+}
+
+// Contents of a block in the tree.
+#[derive(Clone, Debug)]
+enum Children {
+    // Innermost block containing these observations in ascending order.
+    Observations(Vec<usize>),
+    // Sub-blocks, sorted by shape and then by their first observation.
+    Blocks(Vec<Node>),
+}
+
+// A block in the tree.
+#[derive(Clone, Debug)]
+struct Node {
+    // How this block is resampled relative to its siblings.
+    shuffle: Shuffle,
+    // Contents of this block.
+    children: Children,
+    // Structure of this block.
+    shape: Shape,
+    // Smallest observation in this block, used for ordering.
+    first: usize,
+}
+
+/// Hierarchy of exchangeability blocks.
+///
+/// Constructing a tree checks that the levels have the same number of
+/// observations and are properly nested. Whether whole-block permutation is
+/// possible is checked separately by
+/// [`ExchangeabilityTree::check_permutable()`], since sign flipping alone
+/// places no requirement on the shape of the blocks.
+#[derive(Clone, Debug)]
+pub struct ExchangeabilityTree {
+    // Blocks at the outermost level.
+    root: Vec<Node>,
+    // How the blocks at each level are resampled, outermost first.
+    shuffles: Vec<Shuffle>,
+    // Total number of observations.
+    n_obs: usize,
+}
+impl ExchangeabilityTree {
+    /// Make a new tree from levels of block ids, outermost first.
+    pub fn new(levels: Vec<BlockLevel>) -> Result<Self, ExchangeabilityError> {
+        let n_obs = match levels.first() {
+            Some(level) => level.ids.len(),
+            None => {
+                return Err(ExchangeabilityError::NoLevels);
+            }
+        };
+
+        // Every level must have the same number of observations.
+        for (level, block_level) in levels.iter().enumerate() {
+            if block_level.ids.len() != n_obs {
+                return Err(ExchangeabilityError::LengthMismatch {
+                    level,
+                    expected: n_obs,
+                    found: block_level.ids.len(),
+                });
+            }
+        }
+
+        // Every block must lie within a single block of the enclosing level.
+        for (level, pair) in levels.windows(2).enumerate() {
+            let mut parents = HashMap::<usize, usize>::new();
+            for (&parent, &block_id) in pair[0].ids.iter().zip(pair[1].ids.iter()) {
+                if *parents.entry(block_id).or_insert(parent) != parent {
+                    return Err(ExchangeabilityError::NotNested {
+                        level: level + 1,
+                        block_id,
+                    });
+                }
+            }
+        }
+
+        let shuffles = levels.iter().map(|level| level.shuffle).collect();
+        let root = Self::build(&levels, 0, (0..n_obs).collect());
+        Ok(Self { root, shuffles, n_obs })
+    }
+
+    /// Make a new tree with a single level of block ids.
+    pub fn flat(block_ids: Array<usize, Dim<[usize; 1]>>, shuffle: Shuffle) -> Self {
+        // A single level is always valid.
+        Self::new(vec![BlockLevel { ids: block_ids, shuffle }]).unwrap()
+    }
+
+    // Recursively build the blocks at `level` containing the observations
+    // `obs`, which are in ascending order.
+    fn build(levels: &[BlockLevel], level: usize, obs: Vec<usize>) -> Vec<Node> {
+        let BlockLevel { ids, shuffle } = &levels[level];
+        // Group observations by block id.
+        let mut blocks = BTreeMap::<usize, Vec<usize>>::new();
+        for i in obs {
+            blocks.entry(ids[i]).or_default().push(i);
+        }
+        let mut nodes: Vec<Node> = blocks
+            .into_values()
+            .map(|obs| {
+                let first = obs[0];
+                let (children, shape) = if level + 1 == levels.len() {
+                    let shape = Shape::Observations(obs.len());
+                    (Children::Observations(obs), shape)
+                } else {
+                    let nodes = Self::build(levels, level + 1, obs);
+                    let shape = Shape::Blocks(nodes.iter().map(|node| node.shape.clone()).collect());
+                    (Children::Blocks(nodes), shape)
+                };
+                Node {
+                    shuffle: *shuffle,
+                    children,
+                    shape,
+                    first,
+                }
+            })
+            .collect();
+        // Canonical order so that blocks of the same shape line up.
+        nodes.sort_by(|a, b| a.shape.cmp(&b.shape).then(a.first.cmp(&b.first)));
+        nodes
+    }
+
+    /// Total number of observations.
+    pub fn n_obs(&self) -> usize {
+        self.n_obs
+    }
+
+    /// Number of levels.
+    pub fn n_levels(&self) -> usize {
+        self.shuffles.len()
+    }
+
+    /// How the blocks at each level are resampled, outermost first.
+    pub fn shuffles(&self) -> &[Shuffle] {
+        &self.shuffles
+    }
+
+    /// Check that whole-block permutation is possible, i.e. that at every
+    /// [`Shuffle::WholeBlock`] level, sibling blocks have identical
+    /// structure.
+    pub fn check_permutable(&self) -> Result<(), ExchangeabilityError> {
+        Self::check_siblings(&self.root, 0)
+    }
+
+    // Recursively check that whole-block siblings have the same shape.
+    fn check_siblings(nodes: &[Node], level: usize) -> Result<(), ExchangeabilityError> {
+        if let Some(first) = nodes.first() {
+            if first.shuffle == Shuffle::WholeBlock
+                && nodes.iter().any(|node| node.shape != first.shape)
+            {
+                return Err(ExchangeabilityError::IncompatibleBlocks { level });
+            }
+        }
+        for node in nodes {
+            if let Children::Blocks(children) = &node.children {
+                Self::check_siblings(children, level + 1)?;
+            }
+        }
+        Ok(())
+    }
+
+    // Draw a random resampling of the observations, returning the source
+    // observation for each position and whether to flip its sign. With
+    // `permute`, exchangeable units are permuted with each other, which is
+    // only meaningful if `check_permutable()` succeeds. With `sign_flip`, the
+    // sign of each exchangeable unit is flipped at random.
+    pub(crate) fn sample<R: Rng>(&self, sign_flip: bool, permute: bool, rng: &mut R) -> (Vec<usize>, Vec<bool>) {
+        let mut order: Vec<usize> = (0..self.n_obs).collect();
+        let mut flip = vec![false; self.n_obs];
+        let positions = Self::positions(&self.root);
+        let sources = Self::sample_nodes(&self.root, sign_flip, permute, rng);
+        for (&position, (source, source_flip)) in positions.iter().zip(sources) {
+            order[position] = source;
+            flip[position] = source_flip;
+        }
+        (order, flip)
+    }
+
+    // Observations in these blocks, in canonical order.
+    fn positions(nodes: &[Node]) -> Vec<usize> {
+        let mut positions = Vec::new();
+        for node in nodes {
+            match &node.children {
+                Children::Observations(obs) => positions.extend_from_slice(obs),
+                Children::Blocks(children) => positions.extend(Self::positions(children)),
+            }
+        }
+        positions
+    }
+
+    // Recursively resample sibling blocks, returning the source observation
+    // and sign flip for each position in canonical order.
+    fn sample_nodes<R: Rng>(nodes: &[Node], sign_flip: bool, permute: bool, rng: &mut R) -> Vec<(usize, bool)> {
+        // Resample within each block.
+        let mut units: Vec<Vec<(usize, bool)>> = nodes
+            .iter()
+            .map(|node| match &node.children {
+                Children::Observations(obs) => {
+                    let mut units: Vec<Vec<(usize, bool)>> =
+                        obs.iter().map(|&i| vec![(i, false)]).collect();
+                    // Observations in an innermost within-block block are
+                    // exchangeable individually.
+                    if node.shuffle == Shuffle::WithinBlock {
+                        Self::exchange(&mut units, sign_flip, permute, rng);
+                    }
+                    units.concat()
+                }
+                Children::Blocks(children) => Self::sample_nodes(children, sign_flip, permute, rng),
+            })
+            .collect();
+        // Whole blocks are exchangeable with their siblings.
+        if nodes.first().map(|node| node.shuffle) == Some(Shuffle::WholeBlock) {
+            Self::exchange(&mut units, sign_flip, permute, rng);
+        }
+        units.concat()
+    }
+
+    // Randomly permute and/or sign flip exchangeable units.
+    fn exchange<R: Rng>(units: &mut [Vec<(usize, bool)>], sign_flip: bool, permute: bool, rng: &mut R) {
+        if permute {
+            units.shuffle(rng);
+        }
+        if sign_flip {
+            for unit in units.iter_mut() {
+                if rng.gen::<bool>() {
+                    for (_, flip) in unit.iter_mut() {
+                        *flip = !*flip;
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ndarray::array;
+    use rand::SeedableRng;
+    use rand_chacha::ChaCha8Rng;
+
+    // Families of two siblings nested within two sites, where families can
+    // be swapped within a site but siblings stay together.
+    fn families_within_sites() -> ExchangeabilityTree {
+        ExchangeabilityTree::new(vec![
+            BlockLevel { ids: array![0, 0, 0, 0, 1, 1, 1, 1], shuffle: Shuffle::WithinBlock },
+            BlockLevel { ids: array![0, 0, 1, 1, 2, 2, 3, 3], shuffle: Shuffle::WholeBlock },
+        ])
+        .unwrap()
+    }
+
+    #[test]
+    fn whole_blocks_must_have_the_same_shape() {
+        assert_eq!(families_within_sites().check_permutable(), Ok(()));
+        // Site 0 has a family of two and a family of one.
+        let tree = ExchangeabilityTree::new(vec![
+            BlockLevel { ids: array![0, 0, 0, 1, 1], shuffle: Shuffle::WithinBlock },
+            BlockLevel { ids: array![0, 0, 1, 2, 2], shuffle: Shuffle::WholeBlock },
+        ])
+        .unwrap();
+        assert_eq!(tree.check_permutable(), Err(ExchangeabilityError::IncompatibleBlocks { level: 1 }));
+        // The sites have the same number of observations but differently
+        // shaped families.
+        let tree = ExchangeabilityTree::new(vec![
+            BlockLevel { ids: array![0, 0, 0, 0, 1, 1, 1, 1], shuffle: Shuffle::WholeBlock },
+            BlockLevel { ids: array![0, 0, 1, 1, 2, 3, 3, 3], shuffle: Shuffle::WithinBlock },
+        ])
+        .unwrap();
+        assert_eq!(tree.check_permutable(), Err(ExchangeabilityError::IncompatibleBlocks { level: 0 }));
+    }
+
+    #[test]
+    fn nesting_is_checked() {
+        let err = ExchangeabilityTree::new(vec![
+            BlockLevel { ids: array![0, 0, 1, 1], shuffle: Shuffle::WithinBlock },
+            BlockLevel { ids: array![0, 1, 1, 2], shuffle: Shuffle::WholeBlock },
+        ])
+        .unwrap_err();
+        assert_eq!(err, ExchangeabilityError::NotNested { level: 1, block_id: 1 });
+    }
+
+    #[test]
+    fn sample_permutes_only_within_the_tree() {
+        let tree = families_within_sites();
+        let mut rng = ChaCha8Rng::seed_from_u64(3);
+        let (mut swapped, mut flipped) = (false, false);
+        for _ in 0..100 {
+            let (order, flip) = tree.sample(true, true, &mut rng);
+            let mut sorted = order.clone();
+            sorted.sort_unstable();
+            assert_eq!(sorted, (0..8).collect::<Vec<_>>());
+            for family in 0..4 {
+                let (first, second) = (2 * family, 2 * family + 1);
+                // Families stay within their site and siblings stay together,
+                // in order, with the same sign.
+                assert_eq!(order[first] / 4, first / 4, "{:?}", order);
+                assert_eq!(order[first] % 2, 0, "{:?}", order);
+                assert_eq!(order[second], order[first] + 1, "{:?}", order);
+                assert_eq!(flip[second], flip[first], "{:?}", flip);
+            }
+            swapped |= order[0] != 0;
+            flipped |= flip[0];
+        }
+        assert!(swapped && flipped);
+    }
+
+    #[test]
+    fn sample_flips_innermost_observations_individually() {
+        let tree = ExchangeabilityTree::flat(array![0, 0, 1, 1], Shuffle::WithinBlock);
+        let mut rng = ChaCha8Rng::seed_from_u64(5);
+        let mut differ = false;
+        for _ in 0..100 {
+            let (order, flip) = tree.sample(true, false, &mut rng);
+            assert_eq!(order, vec![0, 1, 2, 3]);
+            differ |= flip[0] != flip[1];
+        }
+        assert!(differ);
+        for _ in 0..100 {
+            let (order, flip) = tree.sample(false, true, &mut rng);
+            assert!(order[..2].iter().all(|&i| i < 2) && order[2..].iter().all(|&i| i >= 2), "{:?}", order);
+            assert_eq!(flip, vec![false; 4]);
+        }
+    }
+}
@@ -0,0 +1,379 @@
+//! Reading and writing HDF5 files, with the `hdf5` cargo feature.
+//!
+//! Mock data can be saved to and loaded from HDF5 files with
+//! [`MockData::save_hdf5_file()`](crate::MockData::save_hdf5_file) and
+//! [`MockData::from_hdf5_file()`](crate::MockData::from_hdf5_file), with one
+//! dataset per array, named as in the npz files. [`H5File`] writes SwE
+//! results, such as the 3-D `covB`, in the same way.
+//!
+//! Datasets are chunked along their last axis, which is the features axis of
+//! `resid`, `y`, `beta` and `covB`, and each chunk holds the whole of the
+//! other axes. Reading or writing a range of features with
+//! [`H5File::read_features()`] and [`H5File::write_features()`] then only
+//! touches the chunks holding those features, so that a computation over
+//! chunks of features never holds a whole array in memory. Chunks may be
+//! compressed with deflate, see [`Compression`].
+//!
+//! This module needs the HDF5 library to be installed on the system, see the
+//! [`hdf5`](https://docs.rs/hdf5) crate.
+//!
+//! Example, computing the SwE variance of the second predictor for 1000
+//! features at a time:
+//! ```no_run
+//! use swe_mockup::h5::H5File;
+//! use swe_mockup::npz::Compression;
+//! use swe_mockup::{swe, MockData};
+//! let n_feat = H5File::open("mock-data.h5")?.shape("resid")?[1];
+//! let mut results = H5File::create("results.h5", Compression::Deflate { level: 4 })?;
+//! results.create_array::<f64>("var", &[n_feat])?;
+//! for start in (0..n_feat).step_by(1000) {
+//!     let features = start..n_feat.min(start + 1000);
+//!     let mock_data = MockData::<f64>::from_hdf5_features("mock-data.h5", features)?;
+//!     let blocks = swe::block_indices(mock_data.block_ids.view());
+//!     let var = swe::variance(mock_data.x_pinv.view(), mock_data.resid.view(), &blocks, 1);
+//!     results.write_features("var", start, &var)?;
+//! }
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+
+use crate::mat::MatElement;
+use crate::npz::{ArraySink, ArraySource, Compression, H5Element};
+use crate::Error;
+use hdf5::types::TypeDescriptor;
+use hdf5::{Dataset, H5Type, Hyperslab, Selection, SliceOrIndex};
+use ndarray::{Array, ArrayBase, Data, Dimension};
+use ndarray_npy::{ReadableElement, WritableElement};
+use std::ops::Range;
+use std::path::Path;
+
+// Target size of a chunk in bytes. Chunks of about a megabyte fit in HDF5's
+// default chunk cache while keeping the number of chunks manageable.
+const CHUNK_BYTES: usize = 1 << 20;
+
+/// HDF5 file holding named arrays, e.g. mock data or SwE results.
+pub struct H5File {
+    // Underlying file
+    file: hdf5::File,
+    // Compression of new datasets
+    compression: Compression,
+}
+impl H5File {
+    /// Create a new file, replacing any existing file. Datasets added to the
+    /// file are compressed with `compression`. Returns an error, before
+    /// creating the file, if the compression is not valid, see
+    /// [`Compression::is_valid()`].
+    pub fn create<P: AsRef<Path>>(path: P, compression: Compression) -> Result<Self, Error> {
+        if !compression.is_valid() {
+            return Err(Error::InvalidCompression(compression));
+        }
+        let file = hdf5::File::create(path)?;
+        Ok(Self { file, compression })
+    }
+
+    /// Open an existing file for reading only.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        let file = hdf5::File::open(path)?;
+        Ok(Self {
+            file,
+            compression: Compression::Stored,
+        })
+    }
+
+    /// Open an existing file for reading and writing, e.g. to write slabs of
+    /// features to datasets created earlier. New datasets are compressed with
+    /// `compression`. Returns an error if the compression is not valid.
+    pub fn open_rw<P: AsRef<Path>>(path: P, compression: Compression) -> Result<Self, Error> {
+        if !compression.is_valid() {
+            return Err(Error::InvalidCompression(compression));
+        }
+        let file = hdf5::File::open_rw(path)?;
+        Ok(Self { file, compression })
+    }
+
+    /// Names of the datasets and groups in the root of the file.
+    pub fn names(&self) -> Result<Vec<String>, Error> {
+        Ok(self.file.member_names()?)
+    }
+
+    /// Shape of the named dataset.
+    pub fn shape(&self, name: &str) -> Result<Vec<usize>, Error> {
+        Ok(self.dataset(name)?.shape())
+    }
+
+    /// Add an array as a new dataset, chunked and compressed as described in
+    /// the [module documentation](self).
+    pub fn add_array<S, D>(&mut self, name: &str, array: &ArrayBase<S, D>) -> Result<(), Error>
+    where
+        S: Data,
+        S::Elem: H5Type + Clone,
+        D: Dimension,
+    {
+        self.create_array::<S::Elem>(name, array.shape())?;
+        self.dataset(name)?
+            .write(&array.as_standard_layout())
+            .map_err(Error::hdf5(name))
+    }
+
+    /// Create a new dataset of the given shape, chunked and compressed as
+    /// described in the [module documentation](self), to be filled in with
+    /// [`write_features()`](H5File::write_features). Unwritten elements read
+    /// as zero.
+    pub fn create_array<T: H5Type>(&mut self, name: &str, shape: &[usize]) -> Result<(), Error> {
+        let mut builder = self.file.new_dataset::<T>().shape(shape);
+        // HDF5 cannot chunk scalars or empty datasets, so store them as is.
+        if let Some(chunk) = chunk_shape(shape, std::mem::size_of::<T>()) {
+            builder = builder.chunk(chunk);
+            if let Compression::Deflate { level } = self.compression {
+                // Shuffling the bytes of each element first groups the
+                // exponents of floating point numbers, which compress well.
+                builder = builder.shuffle().deflate(level as u8);
+            }
+        }
+        builder.create(name).map_err(Error::hdf5(name))?;
+        Ok(())
+    }
+
+    /// Write a slab of features to the named dataset, starting at feature
+    /// `first`. The features are the last axis, and the slab must span the
+    /// whole of the other axes of the dataset.
+    pub fn write_features<S, D>(&mut self, name: &str, first: usize, slab: &ArrayBase<S, D>) -> Result<(), Error>
+    where
+        S: Data,
+        S::Elem: H5Type + Clone,
+        D: Dimension,
+    {
+        let n = slab.shape().last().copied().unwrap_or(1);
+        let dataset = self.dataset(name)?;
+        let selection = features_selection(dataset.ndim(), first..first + n);
+        dataset
+            .write_slice(&slab.as_standard_layout(), selection)
+            .map_err(Error::hdf5(name))
+    }
+
+    /// Read a range of features from the named dataset, spanning the whole of
+    /// the other axes. The elements are converted to T if the dataset has a
+    /// different numeric type.
+    pub fn read_features<T, D>(&self, name: &str, features: Range<usize>) -> Result<Array<T, D>, Error>
+    where
+        T: H5Type,
+        D: Dimension,
+    {
+        let dataset = self.dataset(name)?;
+        let selection = features_selection(dataset.ndim(), features);
+        dataset.read_slice(selection).map_err(Error::hdf5(name))
+    }
+
+    /// Read the whole of the named dataset. The elements are converted to T if
+    /// the dataset has a different numeric type.
+    pub fn by_name<T, D>(&self, name: &str) -> Result<Array<T, D>, Error>
+    where
+        T: H5Type,
+        D: Dimension,
+    {
+        self.dataset(name)?.read().map_err(Error::hdf5(name))
+    }
+
+    /// Flush the file and close it, reporting any error writing it.
+    pub fn close(self) -> Result<(), Error> {
+        self.file.flush()?;
+        Ok(self.file.close()?)
+    }
+
+    // Open the named dataset.
+    fn dataset(&self, name: &str) -> Result<Dataset, Error> {
+        self.file.dataset(name).map_err(Error::hdf5(name))
+    }
+
+    // Whether the named dataset has elements of type T, as opposed to
+    // elements HDF5 could convert to T.
+    fn has_type<T: H5Type>(&self, name: &str) -> Result<bool, Error> {
+        let dtype = self.dataset(name)?.dtype().map_err(Error::hdf5(name))?;
+        let descriptor: TypeDescriptor = dtype.to_descriptor().map_err(Error::hdf5(name))?;
+        Ok(descriptor == T::type_descriptor())
+    }
+}
+impl ArraySink for H5File {
+    fn add_array<S, D>(&mut self, name: &str, array: &ArrayBase<S, D>) -> Result<(), Error>
+    where
+        S: Data,
+        S::Elem: WritableElement + MatElement + H5Element,
+        D: Dimension,
+    {
+        H5File::add_array(self, name, array)
+    }
+}
+impl ArraySource for H5File {
+    fn has(&self, name: &str) -> bool {
+        self.file.link_exists(name)
+    }
+
+    // Only reads elements of exactly type T, leaving conversions to the
+    // caller as for the other file formats.
+    fn read<T, D>(&mut self, name: &str) -> Result<Option<Array<T, D>>, Error>
+    where
+        T: ReadableElement + MatElement + H5Element,
+        D: Dimension,
+    {
+        if !self.has_type::<T>(name)? {
+            return Ok(None);
+        }
+        self.by_name(name).map(Some)
+    }
+}
+
+// Source of the arrays of mock data in an HDF5 file which reads only a range
+// of features of the arrays with features along their last axis.
+pub(crate) struct FeatureSlab<'a> {
+    // File to read from
+    pub(crate) file: &'a H5File,
+    // Names of the arrays with features along their last axis
+    pub(crate) arrays: &'a [&'a str],
+    // Range of features to read
+    pub(crate) features: Range<usize>,
+}
+impl ArraySource for FeatureSlab<'_> {
+    fn has(&self, name: &str) -> bool {
+        self.file.file.link_exists(name)
+    }
+
+    fn read<T, D>(&mut self, name: &str) -> Result<Option<Array<T, D>>, Error>
+    where
+        T: ReadableElement + MatElement + H5Element,
+        D: Dimension,
+    {
+        if !self.file.has_type::<T>(name)? {
+            return Ok(None);
+        }
+        if self.arrays.contains(&name) {
+            self.file.read_features(name, self.features.clone()).map(Some)
+        } else {
+            self.file.by_name(name).map(Some)
+        }
+    }
+}
+
+// Chunk shape for a dataset: the whole of every axis but the last, and as
+// many elements along the last axis as fit in about CHUNK_BYTES, but at least
+// one. None for scalars and empty datasets, which cannot be chunked.
+fn chunk_shape(shape: &[usize], elem_size: usize) -> Option<Vec<usize>> {
+    let (&last, rest) = shape.split_last()?;
+    if shape.contains(&0) {
+        return None;
+    }
+    let slice_bytes = rest.iter().product::<usize>().saturating_mul(elem_size);
+    let n = (CHUNK_BYTES / slice_bytes.max(1)).clamp(1, last);
+    Some(rest.iter().copied().chain(std::iter::once(n)).collect())
+}
+
+// Selection of a range of features along the last axis of a dataset with
+// `ndim` axes, spanning the whole of the other axes.
+fn features_selection(ndim: usize, features: Range<usize>) -> Selection {
+    let mut slices = vec![SliceOrIndex::from(..); ndim.saturating_sub(1)];
+    slices.push(SliceOrIndex::from(features));
+    Selection::from(Hyperslab::from(slices))
+}
+
+#[cfg(all(test, feature = "hdf5"))]
+mod tests {
+    use super::*;
+    use crate::{Features, MockData, MockParams};
+    use ndarray::{s, Array1, Array2, Array3};
+    use std::num::NonZeroUsize;
+    use std::path::PathBuf;
+
+    // Path of a temporary file for a test.
+    fn temp_path(test: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("swe-mockup-{}-{}.h5", test, std::process::id()))
+    }
+
+    #[test]
+    fn chunks_span_all_but_the_last_axis() {
+        assert_eq!(chunk_shape(&[], 8), None);
+        assert_eq!(chunk_shape(&[4, 0], 8), None);
+        assert_eq!(chunk_shape(&[10], 8), Some(vec![10]));
+        assert_eq!(chunk_shape(&[1 << 10, 1 << 10], 8), Some(vec![1 << 10, 128]));
+        assert_eq!(chunk_shape(&[1 << 20, 3], 8), Some(vec![1 << 20, 1]));
+    }
+
+    #[test]
+    fn arrays_round_trip() {
+        let path = temp_path("round-trip");
+        let x = Array2::from_shape_fn((3, 5), |(i, j)| (i * 5 + j) as f64 / 7.);
+        let ids = Array1::from(vec![0u64, 0, 1]);
+        for compression in [Compression::Stored, Compression::Deflate { level: 4 }] {
+            let mut file = H5File::create(&path, compression).unwrap();
+            file.add_array("x", &x).unwrap();
+            file.add_array("x_t", &x.t()).unwrap();
+            file.add_array("ids", &ids).unwrap();
+            file.close().unwrap();
+
+            let mut file = H5File::open(&path).unwrap();
+            let mut names = file.names().unwrap();
+            names.sort();
+            assert_eq!(names, ["ids", "x", "x_t"]);
+            assert_eq!(file.shape("x").unwrap(), [3, 5]);
+            assert_eq!(file.by_name::<f64, _>("x").unwrap(), x);
+            assert_eq!(file.by_name::<f64, _>("x_t").unwrap(), x.t());
+            assert_eq!(file.by_name::<u64, _>("ids").unwrap(), ids);
+            // Only exact types are read through ArraySource.
+            assert!(ArraySource::read::<f32, ndarray::Ix2>(&mut file, "x").unwrap().is_none());
+        }
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn invalid_compression_is_an_error() {
+        let path = temp_path("invalid");
+        let compression = Compression::Deflate { level: 10 };
+        assert!(matches!(H5File::create(&path, compression), Err(Error::InvalidCompression(_))));
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn slabs_of_features_round_trip() {
+        let path = temp_path("slabs");
+        let cov = Array3::from_shape_fn((2, 2, 10), |(i, j, k)| (i * 100 + j * 10 + k) as f64);
+        let mut file = H5File::create(&path, Compression::Deflate { level: 1 }).unwrap();
+        file.create_array::<f64>("cov", &[2, 2, 10]).unwrap();
+        for start in (0..10).step_by(3) {
+            let end = 10.min(start + 3);
+            file.write_features("cov", start, &cov.slice(s![.., .., start..end])).unwrap();
+        }
+        file.close().unwrap();
+
+        let file = H5File::open(&path).unwrap();
+        assert_eq!(file.by_name::<f64, ndarray::Ix3>("cov").unwrap(), cov);
+        let slab: Array3<f64> = file.read_features("cov", 4..7).unwrap();
+        assert_eq!(slab, cov.slice(s![.., .., 4..7]));
+        // Elements are converted to the requested type.
+        let slab: Array3<f32> = file.read_features("cov", 9..10).unwrap();
+        assert_eq!(slab, cov.slice(s![.., .., 9..10]).mapv(|x| x as f32));
+        drop(file);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn mock_data_round_trips_whole_and_by_features() {
+        let path = temp_path("mock-data");
+        let mock_data = MockData::<f64>::from_params(MockParams {
+            n_obs: NonZeroUsize::new(20).unwrap(),
+            features: Features::Independent { n_feat: NonZeroUsize::new(12).unwrap() },
+            seed: Some(3),
+            ..MockParams::default()
+        });
+        mock_data.save_hdf5_file(&path, Compression::Deflate { level: 4 }).unwrap();
+
+        let loaded = MockData::<f64>::from_hdf5_file(&path).unwrap();
+        assert_eq!(loaded.block_ids, mock_data.block_ids);
+        assert_eq!(loaded.x_pinv, mock_data.x_pinv);
+        assert_eq!(loaded.resid, mock_data.resid);
+        assert_eq!(loaded.params, mock_data.params);
+
+        let slab = MockData::<f64>::from_hdf5_features(&path, 5..9).unwrap();
+        assert_eq!(slab.x_pinv, mock_data.x_pinv);
+        assert_eq!(slab.resid, mock_data.resid.slice(s![.., 5..9]));
+        assert!(MockData::<f64>::from_hdf5_features(&path, 10..13).is_err());
+        assert!(MockData::<f64>::from_hdf5_features(&path, 4..4).is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+}
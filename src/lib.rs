@@ -1,17 +1,45 @@
 //! Shared functionality between benchmarking examples.
 
+pub mod adjust;
+pub mod blocks;
+pub mod checkpoint;
+pub mod error;
+pub mod exchange;
+#[cfg(feature = "hdf5")]
+pub mod h5;
+pub mod mat;
+pub mod maxstat;
+pub mod npz;
+pub mod permute;
+pub mod phenotype;
+pub mod swe;
+#[cfg(feature = "arrow")]
+pub mod table;
+
+pub use error::Error;
+
 use std::io::{Read, Seek, Write};
+use std::path::Path;
 use std::num::NonZeroUsize;
-use ndarray::{Array, Axis, Dim, s};
-use ndarray_rand::{RandomExt, SamplingStrategy};
-use ndarray_npy::{NpzReader, NpzWriter, ReadableElement, ReadNpzError, WritableElement, WriteNpzError};
+use ndarray::{Array, ArrayView, ArrayViewMut, Axis, Dim, s};
+use ndarray_rand::RandomExt;
+use ndarray_npy::{ReadableElement, WritableElement};
+use rand::SeedableRng;
+use rand_chacha::ChaCha8Rng;
+use rayon::iter::{IndexedParallelIterator, IntoParallelIterator, ParallelIterator};
 use rand_distr::{Distribution, StandardNormal, Uniform};
+use blocks::{BlockIds, BlockLabels};
+use exchange::{BlockLevel, ExchangeabilityTree, Shuffle};
+use mat::{MatElement, MatReader, MatWriter};
+use npz::{ArraySink, ArraySource, Compression, H5Element, MatSizes, NpyDir, NpzSource, NpzWriter};
+#[cfg(feature = "hdf5")]
+use h5::{FeatureSlab, H5File};
 
 /// Range of block sizes.
 /// 
 /// Block size can range from `min_size` up to and including
 /// `max_size_inclusive`.
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub struct BlockSizes {
     // Minimum block size
     min_size: NonZeroUsize,
@@ -65,6 +93,11 @@ impl BlockSizes {
     pub fn max_size_inclusive(&self) -> NonZeroUsize {
         self.max_size_inclusive
     }
+
+    // Uniform distribution of block sizes.
+    fn uniform(&self) -> Uniform<u64> {
+        Uniform::new_inclusive(self.min_size.get() as u64, self.max_size_inclusive.get() as u64)
+    }
 }
 impl Default for BlockSizes {
     fn default() -> Self {
@@ -77,25 +110,752 @@ impl std::fmt::Display for BlockSizes {
     }
 }
 
+/// Histogram of block sizes for sampling from an empirical distribution.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SizeHistogram {
+    // Distinct block sizes
+    sizes: Vec<u64>,
+    // Number of blocks of each size
+    counts: Vec<u64>,
+}
+impl SizeHistogram {
+    /// Make a new `SizeHistogram` where `counts[i]` is the number of blocks
+    /// of size `i + 1`. Returns None if there are no blocks.
+    pub fn new(counts: &[usize]) -> Option<Self> {
+        let (sizes, counts) = counts
+            .iter()
+            .enumerate()
+            .filter(|(_, &count)| count > 0)
+            .map(|(i, &count)| (i as u64 + 1, count as u64))
+            .unzip();
+        Self::from_parts(sizes, counts)
+    }
+
+    /// Make a new `SizeHistogram` from the sizes of the blocks in an existing
+    /// vector of block ids, e.g. from real data. Returns None if there are no
+    /// blocks.
+    pub fn from_block_ids(block_ids: ArrayView<usize, Dim<[usize; 1]>>) -> Option<Self> {
+        // Count the observations in each block, then the blocks of each size.
+        let mut block_sizes = std::collections::HashMap::<usize, u64>::new();
+        for &block_id in block_ids.iter() {
+            *block_sizes.entry(block_id).or_default() += 1;
+        }
+        let mut histogram = std::collections::BTreeMap::<u64, u64>::new();
+        for size in block_sizes.into_values() {
+            *histogram.entry(size).or_default() += 1;
+        }
+        let (sizes, counts) = histogram.into_iter().unzip();
+        Self::from_parts(sizes, counts)
+    }
+
+    // Make a new `SizeHistogram` from distinct sizes in ascending order and
+    // their nonzero counts.
+    fn from_parts(sizes: Vec<u64>, counts: Vec<u64>) -> Option<Self> {
+        if sizes.is_empty() {
+            None
+        } else {
+            Some(Self { sizes, counts })
+        }
+    }
+
+    /// Total number of blocks in the histogram.
+    pub fn n_blocks(&self) -> u64 {
+        self.counts.iter().sum()
+    }
+}
+
+/// Distribution of block sizes.
+///
+/// Cluster imbalance strongly affects both the accuracy of the SwE and the
+/// performance of the kernels computing it, so the mock data can be
+/// generated with a variety of block sizes. Every block has at least one
+/// observation.
+#[derive(Clone, Debug, PartialEq)]
+pub enum BlockSizeDistribution {
+    /// Every block has the same size.
+    Fixed(NonZeroUsize),
+    /// Sizes are uniformly distributed within a range.
+    Uniform(BlockSizes),
+    /// Sizes are one plus a Poisson variate, with a mean size of `mean`,
+    /// which must be greater than one.
+    Poisson {
+        /// Mean block size
+        mean: f64,
+    },
+    /// Sizes are geometrically distributed, with a mean size of `mean`, which
+    /// must be at least one. Many blocks are small and a few are large.
+    Geometric {
+        /// Mean block size
+        mean: f64,
+    },
+    /// Sizes are sampled from a histogram of block sizes, e.g. from real
+    /// data.
+    Empirical(SizeHistogram),
+    /// Mixture of a few giant blocks and many tiny blocks. Each block is giant
+    /// with probability `giant_fraction`.
+    GiantMixture {
+        /// Probability that a block is giant, between zero and one
+        giant_fraction: f64,
+        /// Range of sizes of the giant blocks
+        giant: BlockSizes,
+        /// Range of sizes of the tiny blocks
+        tiny: BlockSizes,
+    },
+}
+impl BlockSizeDistribution {
+    /// Whether the parameters of the distribution are valid.
+    pub fn is_valid(&self) -> bool {
+        match *self {
+            BlockSizeDistribution::Poisson { mean } => mean > 1. && mean.is_finite(),
+            BlockSizeDistribution::Geometric { mean } => mean >= 1. && mean.is_finite(),
+            BlockSizeDistribution::GiantMixture { giant_fraction, .. } => (0. ..=1.).contains(&giant_fraction),
+            _ => true,
+        }
+    }
+
+    // Make a sampler for this distribution. Panics if the parameters are not
+    // valid.
+    fn sampler(&self) -> BlockSizeSampler {
+        assert!(self.is_valid(), "Invalid block size distribution: {}", self);
+        match self {
+            BlockSizeDistribution::Fixed(size) => BlockSizeSampler::Fixed(size.get() as u64),
+            BlockSizeDistribution::Uniform(sizes) => BlockSizeSampler::Uniform(sizes.uniform()),
+            BlockSizeDistribution::Poisson { mean } => {
+                BlockSizeSampler::Poisson(rand_distr::Poisson::new(mean - 1.).unwrap())
+            }
+            BlockSizeDistribution::Geometric { mean } => {
+                BlockSizeSampler::Geometric(rand_distr::Geometric::new(1. / mean).unwrap())
+            }
+            BlockSizeDistribution::Empirical(histogram) => BlockSizeSampler::Empirical(
+                histogram.sizes.clone(),
+                rand_distr::WeightedIndex::new(&histogram.counts).unwrap(),
+            ),
+            BlockSizeDistribution::GiantMixture { giant_fraction, giant, tiny } => {
+                BlockSizeSampler::GiantMixture(*giant_fraction, giant.uniform(), tiny.uniform())
+            }
+        }
+    }
+}
+impl Default for BlockSizeDistribution {
+    fn default() -> Self {
+        BlockSizeDistribution::Uniform(BlockSizes::default())
+    }
+}
+impl std::fmt::Display for BlockSizeDistribution {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            BlockSizeDistribution::Fixed(size) => write!(f, "fixed at {}", size),
+            BlockSizeDistribution::Uniform(sizes) => write!(f, "uniform from {} up to and including {}", sizes.min_size(), sizes.max_size_inclusive()),
+            BlockSizeDistribution::Poisson { mean } => write!(f, "Poisson with mean {}", mean),
+            BlockSizeDistribution::Geometric { mean } => write!(f, "geometric with mean {}", mean),
+            BlockSizeDistribution::Empirical(histogram) => write!(f, "empirical from {} blocks", histogram.n_blocks()),
+            BlockSizeDistribution::GiantMixture { giant_fraction, giant, tiny } => write!(f, "{} giant from {} up to and including {}, otherwise tiny from {} up to and including {}", giant_fraction, giant.min_size(), giant.max_size_inclusive(), tiny.min_size(), tiny.max_size_inclusive()),
+        }
+    }
+}
+
+// Sampler for a block size distribution. Sizes are sampled as u64 rather
+// than usize so that the same seed gives the same sizes on 32- and 64-bit
+// platforms.
+enum BlockSizeSampler {
+    Fixed(u64),
+    Uniform(Uniform<u64>),
+    Poisson(rand_distr::Poisson<f64>),
+    Geometric(rand_distr::Geometric),
+    // Distinct sizes and the distribution of their indices.
+    Empirical(Vec<u64>, rand_distr::WeightedIndex<u64>),
+    // Probability of a giant block, then giant and tiny sizes.
+    GiantMixture(f64, Uniform<u64>, Uniform<u64>),
+}
+impl BlockSizeSampler {
+    // Sample one block size.
+    fn sample<R: rand::Rng>(&self, rng: &mut R) -> usize {
+        let size = match self {
+            BlockSizeSampler::Fixed(size) => *size,
+            BlockSizeSampler::Uniform(uniform) => uniform.sample(rng),
+            BlockSizeSampler::Poisson(poisson) => 1 + poisson.sample(rng) as u64,
+            BlockSizeSampler::Geometric(geometric) => 1 + geometric.sample(rng),
+            BlockSizeSampler::Empirical(sizes, index) => sizes[index.sample(rng)],
+            BlockSizeSampler::GiantMixture(giant_fraction, giant, tiny) => {
+                if rng.gen_bool(*giant_fraction) {
+                    giant.sample(rng)
+                } else {
+                    tiny.sample(rng)
+                }
+            }
+        };
+        size as usize
+    }
+}
+
+/// Order of the observations relative to their blocks.
+///
+/// Blocks scattered throughout the data cause cache misses when computing the
+/// SwE block by block, so the layout determines how much of a penalty the
+/// kernels pay for real data orderings.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum BlockLayout {
+    /// Observations in each block are contiguous, blocks in order.
+    Contiguous,
+    /// Observations are in completely random order.
+    #[default]
+    Shuffled,
+    /// Round robin: the first observation of each block, then the second
+    /// observation of each block, and so on.
+    Interleaved,
+    /// Observations are shuffled locally, moving at most `window` - 1
+    /// positions from where they would be in the contiguous layout. A window
+    /// of one is contiguous, and a window of the number of observations is
+    /// nearly completely shuffled.
+    Local {
+        /// Size of the window within which observations are shuffled
+        window: NonZeroUsize,
+    },
+}
+impl BlockLayout {
+    // Order of the observations, given the block ids in the contiguous
+    // layout.
+    fn order<R: rand::Rng>(&self, block_ids: &[usize], rng: &mut R) -> Vec<usize> {
+        let n_obs = block_ids.len();
+        match *self {
+            BlockLayout::Contiguous => (0..n_obs).collect(),
+            BlockLayout::Shuffled => rand::seq::index::sample(rng, n_obs, n_obs).into_vec(),
+            BlockLayout::Interleaved => {
+                // Sort observations by their position within their block.
+                let mut position = vec![0; n_obs];
+                for i in 1..n_obs {
+                    if block_ids[i] == block_ids[i - 1] {
+                        position[i] = position[i - 1] + 1;
+                    }
+                }
+                let mut order: Vec<usize> = (0..n_obs).collect();
+                order.sort_by_key(|&i| position[i]);
+                order
+            }
+            BlockLayout::Local { window } => {
+                // Sort observations by their position plus a random offset
+                // smaller than the window.
+                let offset = Uniform::new(0, window.get() as u64);
+                let key: Vec<u64> = (0..n_obs).map(|i| i as u64 + offset.sample(rng)).collect();
+                let mut order: Vec<usize> = (0..n_obs).collect();
+                order.sort_by_key(|&i| key[i]);
+                order
+            }
+        }
+    }
+}
+impl std::fmt::Display for BlockLayout {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            BlockLayout::Contiguous => write!(f, "Block layout: contiguous"),
+            BlockLayout::Shuffled => write!(f, "Block layout: shuffled"),
+            BlockLayout::Interleaved => write!(f, "Block layout: interleaved"),
+            BlockLayout::Local { window } => write!(f, "Block layout: shuffled within a window of {}", window),
+        }
+    }
+}
+
+/// Intra-class correlation (ICC) of the residuals within blocks.
+///
+/// Residuals are simulated from a random intercept model with a standard
+/// normal random effect for each block and feature, scaled so that the total
+/// variance of each residual is one and the correlation between residuals in
+/// the same block is the ICC.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Icc {
+    // Smallest ICC
+    min: f64,
+    // Largest ICC
+    max_inclusive: f64,
+}
+impl Icc {
+    /// Make a new `Icc` shared by all features. Returns None if the ICC is
+    /// not between zero and one inclusive.
+    pub fn shared(icc: f64) -> Option<Self> {
+        Self::uniform((icc, icc))
+    }
+
+    /// Make a new `Icc` which varies by feature, drawn from a uniform
+    /// distribution from `min` up to and including `max_inclusive`. Returns
+    /// None if the maximum is less than the minimum or either is not between
+    /// zero and one inclusive.
+    pub fn uniform((min, max_inclusive): (f64, f64)) -> Option<Self> {
+        if (0. ..=1.).contains(&min) && (min..=1.).contains(&max_inclusive) {
+            Some(Self { min, max_inclusive })
+        } else {
+            None
+        }
+    }
+
+    /// Gets the enclosed ICC range as a tuple where the first element is the
+    /// minimum and the second element is the (inclusive) maximum.
+    pub fn get(&self) -> (f64, f64) {
+        (self.min, self.max_inclusive)
+    }
+
+    /// Whether the ICC is zero for every feature, i.e. residuals are
+    /// independent.
+    pub fn is_zero(&self) -> bool {
+        self.max_inclusive == 0.
+    }
+
+    // Sample the ICC for one feature.
+    fn sample<R: rand::Rng>(&self, rng: &mut R) -> f64 {
+        if self.min == self.max_inclusive {
+            self.min
+        } else {
+            rng.gen_range(self.min..=self.max_inclusive)
+        }
+    }
+}
+impl Default for Icc {
+    fn default() -> Self {
+        Self::shared(0.).unwrap()
+    }
+}
+impl std::fmt::Display for Icc {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        if self.min == self.max_inclusive {
+            write!(f, "Intra-class correlation of {}", self.min)
+        } else {
+            write!(f, "Intra-class correlation from {} up to and including {}", self.min, self.max_inclusive)
+        }
+    }
+}
+
+/// Distribution of the simulated errors.
+///
+/// Every distribution is standardized to have mean zero and variance one, so
+/// that the choice of distribution changes only the shape of the errors.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum ErrorDistribution {
+    /// Standard normal distribution.
+    #[default]
+    Normal,
+    /// Heavy-tailed Student's t distribution with `df` degrees of freedom,
+    /// which must be finite and greater than two for the variance to be
+    /// finite. Use [`ErrorDistribution::Normal`] for infinite `df`.
+    StudentT {
+        /// Degrees of freedom
+        df: f64,
+    },
+    /// Skewed normal distribution with the given shape parameter (alpha).
+    /// Positive values give a right skew, negative values a left skew.
+    SkewNormal {
+        /// Shape parameter
+        shape: f64,
+    },
+    /// Mixture of a standard normal distribution with a wider normal
+    /// distribution representing outliers. Each error is an outlier with
+    /// probability `fraction`, in which case its standard deviation is
+    /// multiplied by `scale`.
+    ContaminatedNormal {
+        /// Probability that an error is an outlier, between zero and one
+        fraction: f64,
+        /// Ratio of outlier to non-outlier standard deviation, greater than
+        /// zero and finite when squared
+        scale: f64,
+    },
+}
+impl ErrorDistribution {
+    /// Whether the parameters of the distribution are valid. Every parameter
+    /// must be finite, as must the variance used to standardize the
+    /// distribution.
+    pub fn is_valid(&self) -> bool {
+        match *self {
+            ErrorDistribution::Normal => true,
+            ErrorDistribution::StudentT { df } => df.is_finite() && df > 2.,
+            ErrorDistribution::SkewNormal { shape } => shape.is_finite(),
+            ErrorDistribution::ContaminatedNormal { fraction, scale } => {
+                (0. ..=1.).contains(&fraction) && scale > 0. && (scale * scale).is_finite()
+            }
+        }
+    }
+
+    // Make a sampler for this distribution. Panics if the parameters are not
+    // valid.
+    fn sampler(&self) -> ErrorSampler {
+        assert!(self.is_valid(), "Invalid error distribution: {}", self);
+        match *self {
+            ErrorDistribution::Normal => ErrorSampler::Normal,
+            ErrorDistribution::StudentT { df } => ErrorSampler::StudentT(
+                rand_distr::StudentT::new(df).unwrap(),
+                ((df - 2.) / df).sqrt(),
+            ),
+            ErrorDistribution::SkewNormal { shape } => {
+                // hypot() rather than the square root of 1 + shape^2, which
+                // overflows for large but finite shapes.
+                let delta = shape / shape.hypot(1.);
+                let mean = delta * (2. / std::f64::consts::PI).sqrt();
+                let sd = (1. - 2. * delta * delta / std::f64::consts::PI).sqrt();
+                ErrorSampler::SkewNormal(rand_distr::SkewNormal::new(0., 1., shape).unwrap(), mean, sd)
+            }
+            ErrorDistribution::ContaminatedNormal { fraction, scale } => ErrorSampler::ContaminatedNormal {
+                fraction,
+                scale,
+                sd: ((1. - fraction) + fraction * scale * scale).sqrt(),
+            },
+        }
+    }
+}
+impl std::fmt::Display for ErrorDistribution {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            ErrorDistribution::Normal => write!(f, "Normal errors"),
+            ErrorDistribution::StudentT { df } => write!(f, "Student's t errors with {} degrees of freedom", df),
+            ErrorDistribution::SkewNormal { shape } => write!(f, "Skew normal errors with shape {}", shape),
+            ErrorDistribution::ContaminatedNormal { fraction, scale } => write!(f, "Contaminated normal errors with {} outliers at {} times the standard deviation", fraction, scale),
+        }
+    }
+}
+
+// Sample from the standard normal distribution as f64, which is otherwise
+// ambiguous where it can also be sampled as S.
+fn standard_normal<R: rand::Rng>(rng: &mut R) -> f64 {
+    StandardNormal.sample(rng)
+}
+
+// Sampler for a standardized error distribution.
+enum ErrorSampler {
+    // Standard normal.
+    Normal,
+    // Student's t and the factor to scale it to unit variance.
+    StudentT(rand_distr::StudentT<f64>, f64),
+    // Skew normal and its mean and standard deviation.
+    SkewNormal(rand_distr::SkewNormal<f64>, f64, f64),
+    // Contaminated normal and its standard deviation.
+    ContaminatedNormal { fraction: f64, scale: f64, sd: f64 },
+}
+impl ErrorSampler {
+    // Sample one standardized error.
+    fn sample<R: rand::Rng>(&self, rng: &mut R) -> f64 {
+        match self {
+            ErrorSampler::Normal => StandardNormal.sample(rng),
+            ErrorSampler::StudentT(dist, scale) => dist.sample(rng) * scale,
+            ErrorSampler::SkewNormal(dist, mean, sd) => (dist.sample(rng) - mean) / sd,
+            ErrorSampler::ContaminatedNormal { fraction, scale, sd } => {
+                let x: f64 = StandardNormal.sample(rng);
+                if rng.gen_bool(*fraction) {
+                    x * scale / sd
+                } else {
+                    x / sd
+                }
+            }
+        }
+    }
+}
+
+/// How the variance of the simulated residuals varies.
+///
+/// Heteroskedastic residuals have their standard deviation multiplied by
+/// `exp(log_sd * z - log_sd^2)`, where `z` is a standard normal variate (or a
+/// standardized predictor), so that the average variance is still
+/// approximately one.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum VarianceStructure {
+    /// Every residual has the same variance.
+    #[default]
+    Homoskedastic,
+    /// Variance differs randomly between blocks.
+    ByBlock {
+        /// Standard deviation of the log standard deviation
+        log_sd: f64,
+    },
+    /// Variance increases with the value of predictor `pred`.
+    ByPredictor {
+        /// Index of the predictor
+        pred: usize,
+        /// Change in the log standard deviation per standard deviation of the
+        /// predictor
+        log_sd: f64,
+    },
+    /// Variance differs randomly between features.
+    ByFeature {
+        /// Standard deviation of the log standard deviation
+        log_sd: f64,
+    },
+}
+impl VarianceStructure {
+    // Multiplier for the standard deviation given a standardized variate.
+    fn sd(log_sd: f64, z: f64) -> f64 {
+        (log_sd * z - log_sd * log_sd).exp()
+    }
+
+    // Random multiplier for the standard deviation.
+    fn random_sd<R: rand::Rng>(log_sd: f64, rng: &mut R) -> f64 {
+        VarianceStructure::sd(log_sd, standard_normal(rng))
+    }
+}
+impl std::fmt::Display for VarianceStructure {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            VarianceStructure::Homoskedastic => write!(f, "Homoskedastic residuals"),
+            VarianceStructure::ByBlock { log_sd } => write!(f, "Residual variance varies by block with log standard deviation {}", log_sd),
+            VarianceStructure::ByPredictor { pred, log_sd } => write!(f, "Residual variance varies by predictor {} with log standard deviation {}", pred, log_sd),
+            VarianceStructure::ByFeature { log_sd } => write!(f, "Residual variance varies by feature with log standard deviation {}", log_sd),
+        }
+    }
+}
+
+/// Number and correlation structure of the features.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Features {
+    /// Independent features.
+    Independent {
+        /// Number of features
+        n_feat: NonZeroUsize,
+    },
+    /// Edges of a connectome, i.e. the upper triangle of an `n_nodes` x
+    /// `n_nodes` connectivity matrix in row-major order: (0, 1), (0, 2), ...
+    /// (1, 2), (1, 3), ... and so on.
+    ///
+    /// Each observation has a score at each node from a low-rank model with
+    /// `n_factors` latent factors. A fraction `shared` of the variance of each
+    /// edge comes from the sum of the scores of its two nodes, so that edges
+    /// sharing a node are correlated, as in real functional connectivity.
+    Connectome {
+        /// Number of nodes (parcels), at least two
+        n_nodes: NonZeroUsize,
+        /// Number of latent factors
+        n_factors: NonZeroUsize,
+        /// Fraction of the variance of each edge shared with its nodes,
+        /// between zero and one
+        shared: f64,
+    },
+}
+impl Features {
+    /// Number of features.
+    pub fn n_feat(&self) -> NonZeroUsize {
+        match *self {
+            Features::Independent { n_feat } => n_feat,
+            Features::Connectome { n_nodes, .. } => n_edges(n_nodes.get())
+                .and_then(NonZeroUsize::new)
+                .expect("A connectome needs at least two nodes and a number of edges that fits in usize."),
+        }
+    }
+
+    /// Nodes at either end of each feature if the features are the edges of
+    /// a connectome, in the order of the features, or `None` for independent
+    /// features.
+    pub fn edges(&self) -> Option<Vec<(usize, usize)>> {
+        match *self {
+            Features::Independent { .. } => None,
+            Features::Connectome { n_nodes, .. } => {
+                let n_nodes = n_nodes.get();
+                Some((0..n_nodes).flat_map(|a| ((a + 1)..n_nodes).map(move |b| (a, b))).collect())
+            }
+        }
+    }
+
+    /// Whether the parameters are valid.
+    pub fn is_valid(&self) -> bool {
+        match *self {
+            Features::Independent { .. } => true,
+            Features::Connectome { n_nodes, shared, .. } => {
+                n_edges(n_nodes.get()).is_some_and(|n_edges| n_edges > 0) && (0. ..=1.).contains(&shared)
+            }
+        }
+    }
+}
+// Number of edges between n_nodes nodes, or None if it overflows.
+fn n_edges(n_nodes: usize) -> Option<usize> {
+    Some(n_nodes.checked_mul(n_nodes.checked_sub(1)?)? / 2)
+}
+impl Default for Features {
+    fn default() -> Self {
+        // Upper triangle of a 333-parcel connectome.
+        Features::Independent {
+            n_feat: NonZeroUsize::new(((333 * 333) - 333) / 2).unwrap(),
+        }
+    }
+}
+impl std::fmt::Display for Features {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Features::Independent { n_feat } => write!(f, "Number of features: {}", n_feat),
+            // Not the number of edges, which may not be valid.
+            Features::Connectome { n_nodes, n_factors, shared } => write!(f, "Number of features: edges between {} nodes, sharing {} of their variance through {} factors", n_nodes, shared, n_factors),
+        }
+    }
+}
+
+// Latent node scores for simulating the edges of a connectome.
+struct NodeScores {
+    // Nodes at either end of each edge
+    edges: Vec<(usize, usize)>,
+    // Observations x nodes matrix of scores
+    scores: Array<f64, Dim<[usize; 2]>>,
+    // Standard deviation of the sum of the scores of the nodes of each edge
+    norms: Vec<f64>,
+}
+impl NodeScores {
+    // Simulate scores from a low-rank factor model.
+    fn new<R: rand::Rng>(n_obs: usize, n_nodes: usize, n_factors: usize, rng: &mut R) -> Self {
+        // Random loadings for each node, normalized so the scores have unit
+        // variance.
+        let mut loadings = Array::<f64, _>::random_using((n_nodes, n_factors), StandardNormal, rng);
+        for mut row in loadings.axis_iter_mut(Axis(0)) {
+            let norm = dot_in_order(row.iter().copied(), row.iter().copied()).sqrt();
+            row /= norm;
+        }
+        // Scores are the latent factors of each observation times the
+        // loadings of each node.
+        let factors = Array::<f64, _>::random_using((n_obs, n_factors), StandardNormal, rng);
+        let mut scores = Array::<f64, _>::zeros((n_obs, n_nodes));
+        mul_add_in_order(1., factors.view(), loadings.t(), scores.view_mut());
+        // Variance of the sum of the scores of two nodes depends on the
+        // correlation between the nodes.
+        let n_edges = n_edges(n_nodes).expect("Number of edges overflows.");
+        let mut edges = Vec::with_capacity(n_edges);
+        let mut norms = Vec::with_capacity(n_edges);
+        for a in 0..n_nodes {
+            for b in (a + 1)..n_nodes {
+                let var = 2. + 2. * dot_in_order(loadings.row(a).iter().copied(), loadings.row(b).iter().copied());
+                edges.push((a, b));
+                norms.push(if var > 0. { var.sqrt() } else { 1. });
+            }
+        }
+        Self { edges, scores, norms }
+    }
+}
+
+/// Parameters for generating the design matrix, X.
+///
+/// The columns of X are an intercept, then a dummy (indicator) variable for
+/// each site except the first, then continuous covariates for the remaining
+/// predictors. Every observation in a block is from the same site.
+#[derive(Clone, Copy, Debug)]
+pub struct DesignParams {
+    /// Number of sites, one for no site effects
+    pub n_sites: NonZeroUsize,
+    /// Correlation of each continuous covariate within blocks, i.e. how much
+    /// the covariates are confounded with block membership
+    pub covariate_icc: Icc,
+    /// Standard deviation of the true regression coefficients, β, which are
+    /// drawn from a normal distribution with mean zero. Zero for data
+    /// generated under the null hypothesis.
+    pub beta_sd: f64,
+    /// Whether to keep X, β and the responses, Y, in the mock data. Y is as
+    /// large as the residuals, so by default only `x_pinv` and `resid` are
+    /// kept.
+    pub keep_model: bool,
+}
+impl Default for DesignParams {
+    fn default() -> Self {
+        Self {
+            n_sites: NonZeroUsize::new(1).unwrap(),
+            covariate_icc: Icc::default(),
+            beta_sd: 0.,
+            keep_model: false,
+        }
+    }
+}
+impl std::fmt::Display for DesignParams {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Design with {} sites, covariate {}, coefficients with standard deviation {}", self.n_sites, self.covariate_icc.to_string().to_lowercase(), self.beta_sd)?;
+        if self.keep_model {
+            write!(f, ", keeping X, Y and β")?;
+        }
+        Ok(())
+    }
+}
+
+/// Parameters for one level of blocks enclosing the blocks of the next level
+/// in, e.g. sites enclosing families.
+#[derive(Clone, Debug)]
+pub struct NestingParams {
+    /// Distribution of the number of inner blocks in each enclosing block
+    pub sizes: BlockSizeDistribution,
+    /// How the enclosing blocks are resampled in a permutation test
+    pub shuffle: Shuffle,
+}
+impl std::fmt::Display for NestingParams {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Enclosing blocks with number of inner blocks {}, shuffled {}", self.sizes, self.shuffle)
+    }
+}
+
 /// Parameters for generating mock data.
 #[derive(Clone, Debug)]
 pub struct MockParams {
     /// Number of observations
     pub n_obs: NonZeroUsize,
-    /// Number of features/edges
-    pub n_feat: NonZeroUsize,
+    /// Number and structure of features/edges
+    pub features: Features,
     /// Number of predictors/covariates
     pub n_pred: NonZeroUsize,
-    // Range of possible block/group sizes
-    pub block_sizes: BlockSizes,
+    // Distribution of block/group sizes
+    pub block_sizes: BlockSizeDistribution,
+    /// Order of the observations relative to their blocks
+    pub layout: BlockLayout,
+    /// Design matrix and regression coefficients
+    pub design: DesignParams,
+    /// Correlation of residuals within each block
+    pub icc: Icc,
+    /// Distribution of the errors
+    pub errors: ErrorDistribution,
+    /// How the variance of the residuals varies
+    pub variance: VarianceStructure,
+    /// Levels of blocks enclosing the blocks, outermost first. Empty for a
+    /// single level of blocks.
+    pub nesting: Vec<NestingParams>,
+    /// Seed for the random number generator. The same seed always generates
+    /// identical data on any machine and with any number of threads. If
+    /// `None`, the generator is seeded from system entropy and every run
+    /// generates different data.
+    pub seed: Option<u64>,
 }
 impl Default for MockParams {
     fn default() -> Self {
         Self {
             n_obs: NonZeroUsize::new(8192).unwrap(),
-            n_feat: NonZeroUsize::new(((333 * 333) - 333) / 2).unwrap(),
+            features: Features::default(),
             n_pred: NonZeroUsize::new(8).unwrap(),
-            block_sizes: BlockSizes::default(),
+            block_sizes: BlockSizeDistribution::default(),
+            layout: BlockLayout::default(),
+            design: DesignParams::default(),
+            icc: Icc::default(),
+            errors: ErrorDistribution::default(),
+            variance: VarianceStructure::default(),
+            nesting: Vec::new(),
+            seed: None,
+        }
+    }
+}
+impl MockParams {
+    /// Check that the parameters are valid for generating mock data.
+    pub fn validate(&self) -> Result<(), Error> {
+        let invalid = |msg: String| Err(Error::InvalidParams(msg));
+        if !self.features.is_valid() {
+            return invalid(format!("{}", self.features));
+        }
+        if !self.block_sizes.is_valid() {
+            return invalid(format!("Block sizes {}", self.block_sizes));
+        }
+        if let Some(nesting) = self.nesting.iter().find(|nesting| !nesting.sizes.is_valid()) {
+            return invalid(format!("{}", nesting));
+        }
+        if !self.errors.is_valid() {
+            return invalid(format!("{}", self.errors));
+        }
+        if self.design.n_sites > self.n_pred {
+            return invalid(format!("{} sites but only {} predictors", self.design.n_sites, self.n_pred));
+        }
+        if !(self.design.beta_sd >= 0. && self.design.beta_sd.is_finite()) {
+            return invalid(format!("{}", self.design));
+        }
+        match self.variance {
+            VarianceStructure::ByPredictor { pred, .. } if pred >= self.n_pred.get() => {
+                invalid(format!("{} but only {} predictors", self.variance, self.n_pred))
+            }
+            VarianceStructure::ByBlock { log_sd }
+            | VarianceStructure::ByPredictor { log_sd, .. }
+            | VarianceStructure::ByFeature { log_sd }
+                if !log_sd.is_finite() =>
+            {
+                invalid(format!("{}", self.variance))
+            }
+            _ => Ok(()),
         }
     }
 }
@@ -103,133 +863,642 @@ impl std::fmt::Display for MockParams {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         writeln!(f, "Mock data parameters:")?;
         writeln!(f, "Number of observations: {}", self.n_obs)?;
-        writeln!(f, "Number of features: {}", self.n_feat)?;
+        writeln!(f, "{}", self.features)?;
         writeln!(f, "Number of predictors: {}", self.n_pred)?;
-        writeln!(f, "{}", self.block_sizes)?;
+        writeln!(f, "Block sizes {}", self.block_sizes)?;
+        writeln!(f, "{}", self.layout)?;
+        writeln!(f, "{}", self.design)?;
+        writeln!(f, "{}", self.icc)?;
+        writeln!(f, "{}", self.errors)?;
+        writeln!(f, "{}", self.variance)?;
+        for nesting in self.nesting.iter() {
+            writeln!(f, "{}", nesting)?;
+        }
+        match self.seed {
+            Some(seed) => writeln!(f, "Random seed: {}", seed)?,
+            None => writeln!(f, "Random seed: from entropy")?,
+        }
         Ok(())
     }
 }
 
+// Number of features (columns of residuals) generated together in one chunk
+// from one random number stream. Fixed so that the generated data do not
+// depend on the number of threads.
+const CHUNK_FEAT: usize = 64;
+
+// Random number generator for one chunk of data. Each chunk draws from its
+// own stream, derived deterministically from the seed. Stream 0 is reserved
+// for data generated serially.
+fn chunk_rng(seed: u64, chunk: usize) -> ChaCha8Rng {
+    let mut rng = ChaCha8Rng::seed_from_u64(seed);
+    rng.set_stream(chunk as u64 + 1);
+    rng
+}
+
+/// Version of the npz schema written by [`MockData::save_npz_file()`] as
+/// `(major, minor)`. Files without a version array are treated as version
+/// `(1, 0)`, the original four arrays. Minor versions only add optional
+/// arrays, which older readers ignore. Files with a newer major version are
+/// rejected.
+pub const NPZ_SCHEMA_VERSION: (u64, u64) = (2, 0);
+
+// Names of all the arrays MockData::save() may write, required or optional.
+const NPZ_ARRAYS: &[&str] = &[
+    "schema_version",
+    "n_blocks",
+    "block_ids",
+    "x_pinv",
+    "resid",
+    "block_labels",
+    "block_label_offsets",
+    "x",
+    "y",
+    "beta",
+    "nesting_ids",
+    "nesting_shuffle",
+    "params",
+];
+
 /// Mock data of numeric type S, e.g. f64.
 pub struct MockData<S: Clone> {
-    /// Total number of blocks
-    pub n_blocks: NonZeroUsize,
     /// 1-dimensional vector of block IDs. Each block ID is an integer ranging
-    /// from 0 up to but excluding `n_blocks`.
-    pub block_ids: Array<usize, Dim<[usize; 1]>>,
-    /// Observation x features matrix of residuals
+    /// from 0 up to but excluding the number of blocks, and every block has at
+    /// least one observation.
+    pub block_ids: BlockIds,
+    /// Original label of each block, if the blocks come from labeled data
+    pub block_labels: Option<BlockLabels<String>>,
+    /// Observation x features matrix of residuals of the least squares fit
+    /// of X to Y
     pub resid: Array<S, Dim<[usize; 2]>>,
     /// Predictors x observations pseudoinverse of the design matrix
     pub x_pinv: Array<S, Dim<[usize; 2]>>,
+    /// Observations x predictors design matrix, X, if known
+    pub x: Option<Array<S, Dim<[usize; 2]>>>,
+    /// Observations x features matrix of responses, Y = Xβ + ε, if known
+    pub y: Option<Array<S, Dim<[usize; 2]>>>,
+    /// Predictors x features matrix of true regression coefficients, β, if
+    /// known
+    pub beta: Option<Array<S, Dim<[usize; 2]>>>,
+    /// Levels of blocks enclosing the blocks in `block_ids`, outermost first.
+    /// Each block lies entirely within one block of the enclosing level.
+    pub nesting: Vec<BlockLevel>,
+    /// Description of the parameters used to generate the data, including
+    /// the random seed, if known. It is informational only: it is saved and
+    /// loaded as text and never parsed back into [`MockParams`].
+    pub params: Option<String>,
+}
+// Sum of the products of two sequences, accumulated in order. The linear
+// algebra of mock data generation is done with this rather than with BLAS
+// and LAPACK, whose results depend on the machine, the library and the
+// number of threads, so that a seed gives bit-identical data everywhere.
+fn dot_in_order<S, A, B>(a: A, b: B) -> S
+where
+    S: num_traits::Float,
+    A: IntoIterator<Item = S>,
+    B: IntoIterator<Item = S>,
+{
+    a.into_iter().zip(b).fold(S::zero(), |sum, (a, b)| sum + a * b)
+}
+
+// Add alpha * A * B to C, accumulating each element of A * B in order as in
+// dot_in_order(). Walks the rows of B, which suits row-major arrays.
+fn mul_add_in_order<S>(
+    alpha: S,
+    a: ArrayView<S, Dim<[usize; 2]>>,
+    b: ArrayView<S, Dim<[usize; 2]>>,
+    mut c: ArrayViewMut<S, Dim<[usize; 2]>>,
+) where
+    S: num_traits::Float,
+{
+    let mut sum = vec![S::zero(); b.ncols()];
+    for (a, mut c) in a.rows().into_iter().zip(c.rows_mut()) {
+        sum.fill(S::zero());
+        for (&a, b) in a.iter().zip(b.rows()) {
+            for (sum, &b) in sum.iter_mut().zip(b.iter()) {
+                *sum = *sum + a * b;
+            }
+        }
+        for (c, &sum) in c.iter_mut().zip(sum.iter()) {
+            *c = *c + alpha * sum;
+        }
+    }
+}
+
+// Eigenvalues and eigenvectors, the columns of the returned matrix, of a
+// small symmetric matrix by the cyclic Jacobi method, which rotates away the
+// off-diagonal elements in a fixed order.
+fn symmetric_eigen(mut a: Array<f64, Dim<[usize; 2]>>) -> (Vec<f64>, Array<f64, Dim<[usize; 2]>>) {
+    let n = a.nrows();
+    let mut v = Array::<f64, _>::eye(n);
+    // Convergence is quadratic, so a handful of sweeps is usually enough.
+    for _ in 0..64 {
+        let off = (0..n).flat_map(|p| ((p + 1)..n).map(move |q| (p, q))).fold(0., |sum, (p, q)| sum + a[[p, q]] * a[[p, q]]);
+        let diag = (0..n).fold(0., |sum, i| sum + a[[i, i]] * a[[i, i]]);
+        if off <= f64::EPSILON * f64::EPSILON * diag {
+            break;
+        }
+        for p in 0..n {
+            for q in (p + 1)..n {
+                if a[[p, q]] == 0. {
+                    continue;
+                }
+                // Rotation by the angle which zeroes a[p, q], choosing the
+                // smaller of the two solutions for stability.
+                let theta = (a[[q, q]] - a[[p, p]]) / (2. * a[[p, q]]);
+                let t = theta.signum() / (theta.abs() + (theta * theta + 1.).sqrt());
+                let c = 1. / (t * t + 1.).sqrt();
+                let s = t * c;
+                for k in 0..n {
+                    let (kp, kq) = (a[[k, p]], a[[k, q]]);
+                    a[[k, p]] = c * kp - s * kq;
+                    a[[k, q]] = s * kp + c * kq;
+                }
+                for k in 0..n {
+                    let (pk, qk) = (a[[p, k]], a[[q, k]]);
+                    a[[p, k]] = c * pk - s * qk;
+                    a[[q, k]] = s * pk + c * qk;
+                }
+                for k in 0..n {
+                    let (kp, kq) = (v[[k, p]], v[[k, q]]);
+                    v[[k, p]] = c * kp - s * kq;
+                    v[[k, q]] = s * kp + c * kq;
+                }
+            }
+        }
+    }
+    ((0..n).map(|i| a[[i, i]]).collect(), v)
 }
+
+// Moore-Penrose pseudoinverse of an observations x predictors matrix with few
+// predictors, (X'X)^+ X', from the eigendecomposition of the small Gram
+// matrix X'X = V * diag(lambda) * V'. Eigenvalues below MATLAB's pinv()
+// tolerance relative to the largest are treated as zero. The tolerance is
+// applied to the eigenvalues, the squares of the singular values of X, so
+// that rounding in X'X is not mistaken for a tiny singular value.
+fn pinv<S>(x: ArrayView<S, Dim<[usize; 2]>>) -> Array<S, Dim<[usize; 2]>>
+where
+    S: num_traits::Float,
+{
+    let (n_obs, n_pred) = x.dim();
+    let x = x.mapv(|x| x.to_f64().unwrap());
+    let mut gram = Array::<f64, _>::zeros((n_pred, n_pred));
+    for i in 0..n_pred {
+        for j in 0..=i {
+            let sum = dot_in_order(x.column(i).iter().copied(), x.column(j).iter().copied());
+            gram[[i, j]] = sum;
+            gram[[j, i]] = sum;
+        }
+    }
+    let (lambda, v) = symmetric_eigen(gram);
+    let tol = lambda.iter().fold(0., |max: f64, &lambda| max.max(lambda)) * (n_obs.max(n_pred) as f64) * f64::EPSILON;
+    // Pseudoinverse of the Gram matrix, V * diag(1 / lambda) * V'.
+    let lambda_inv: Vec<f64> = lambda.iter().map(|&lambda| if lambda > tol { 1. / lambda } else { 0. }).collect();
+    let gram_pinv = Array::from_shape_fn((n_pred, n_pred), |(i, j)| {
+        dot_in_order(v.row(i).iter().zip(&lambda_inv).map(|(v, lambda_inv)| v * lambda_inv), v.row(j).iter().copied())
+    });
+    Array::from_shape_fn((n_pred, n_obs), |(i, obs)| {
+        S::from(dot_in_order(gram_pinv.row(i).iter().copied(), x.row(obs).iter().copied())).unwrap()
+    })
+}
+
 impl <S> MockData<S>
 where
-    S: Clone,
+    S: num_traits::Float + Send + Sync,
     StandardNormal: Distribution<S>,
 {
-    /// Randomly generate mock data from mock parameters
+    /// Randomly generate mock data from mock parameters.
+    ///
+    /// Panics if the parameters are not valid, see
+    /// [`MockData::try_from_params()`].
     pub fn from_params(mock_params: MockParams) -> Self {
+        Self::try_from_params(mock_params).unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    /// Randomly generate mock data from mock parameters. Returns an error if
+    /// the parameters are not valid, see [`MockParams::validate()`]. `x`, `y`
+    /// and `beta` are `None` unless [`DesignParams::keep_model`] is set.
+    pub fn try_from_params(mock_params: MockParams) -> Result<Self, Error> {
         // Rename mock_params to something shorter.
-        let mp = mock_params;
+        let mut mp = mock_params;
+        mp.validate()?;
 
-        // Initialize a random number generator.
-        let mut rng = rand::thread_rng();
+        // Initialize a portable random number generator, seeded if requested.
+        let seed = mp.seed.unwrap_or_else(rand::random);
+        let mut rng = ChaCha8Rng::seed_from_u64(seed);
 
-        // Simulate an observations x features matrix of residuals from the
-        // standard normal distribution.
-        let resid = Array::<S, _>::random_using((mp.n_obs.get(), mp.n_feat.get()), StandardNormal, &mut rng);
+        // Record the seed actually used so the data can be regenerated.
+        mp.seed = Some(seed);
+        let params = mp.to_string();
 
-        // Simulate a predictors x observations matrix to stand in for the
-        // pseudoinverse of the design matrix, X.
-        let x_pinv = Array::<S, _>::random_using((mp.n_pred.get(), mp.n_obs.get()), StandardNormal, &mut rng);
-        
         // Simulate a vector of block ids. Each observation is assigned an integer
-        // id from zero to n_blocks. We deliberately simulate non-continuous blocks
-        // to benchmark the effect of cache misses in real data.
+        // id from zero up to but excluding n_blocks. Blocks are contiguous until
+        // the observations are reordered according to the block layout below.
         let (block_ids, n_blocks) = {
-            // Sample block sizes from a uniform distribution.
-            let block_size = Uniform::new_inclusive(mp.block_sizes.min_size().get(), mp.block_sizes.max_size_inclusive().get());
+            // Sample block sizes from the block size distribution.
+            let block_size = mp.block_sizes.sampler();
             // Initialize a zero vector of block ids.
             let mut block_ids = Array::zeros(mp.n_obs.get());
             // Initial conditions for loop.
             let mut block_id = 0;
             let mut block_start = 0;
-            let mut block_end;
             // Loop, assigning ids block by block.
-            while {
-                // Random size for this block.
-                block_end = block_start + block_size.sample(&mut rng);
-                // When we reach the end of block_ids, let the last block default to
-                // an id of zero.
-                block_end <= block_ids.len_of(Axis(0))
-            } {
-                // Pre-increment block id.  The first id to be assigned will
-                // therefore be one.
-                block_id += 1;
+            while block_start < block_ids.len_of(Axis(0)) {
+                // Random size for this block. The last block is truncated at
+                // the end of block_ids.
+                let block_end = std::cmp::min(block_start + block_size.sample(&mut rng), block_ids.len_of(Axis(0)));
                 // Assign ids for this block.
                 block_ids
                     .slice_mut(s![block_start..block_end])
                     .fill(block_id);
                 // Next block starts where this block ends.
+                block_id += 1;
                 block_start = block_end;
             }
-            // Shuffle the order of the block ids.
-            block_ids = block_ids.sample_axis_using(
-                Axis(0),
-                block_ids.len_of(Axis(0)),
-                SamplingStrategy::WithoutReplacement,
-                &mut rng,
-            );
-            // Return the vector of block ids and the last block id.
+            // Return the vector of block ids and the number of blocks.
             (block_ids, block_id)
         };
 
-        Self {
-            n_blocks: NonZeroUsize::new(n_blocks).unwrap(),
-            block_ids,
+        // Simulate enclosing levels of blocks, working outward from the
+        // blocks. Each enclosing block groups a random number of consecutive
+        // blocks from the level below.
+        let mut nesting = Vec::with_capacity(mp.nesting.len());
+        let mut inner_ids = block_ids.clone();
+        for nesting_params in mp.nesting.iter().rev() {
+            // Sample the number of inner blocks from its distribution.
+            let group_size = nesting_params.sizes.sampler();
+            let mut ids = Array::zeros(mp.n_obs.get());
+            let mut group_id = 0;
+            let mut remaining = group_size.sample(&mut rng);
+            for i in 0..ids.len() {
+                // Start a new enclosing block once enough inner blocks have
+                // gone by.
+                if i > 0 && inner_ids[i] != inner_ids[i - 1] {
+                    remaining -= 1;
+                    if remaining == 0 {
+                        group_id += 1;
+                        remaining = group_size.sample(&mut rng);
+                    }
+                }
+                ids[i] = group_id;
+            }
+            inner_ids = ids.clone();
+            nesting.push(BlockLevel { ids, shuffle: nesting_params.shuffle });
+        }
+        nesting.reverse();
+
+        // Reorder the observations according to the block layout, applying
+        // the same order to every level of blocks. Non-contiguous layouts
+        // benchmark the effect of cache misses in real data.
+        let order = mp.layout.order(block_ids.as_slice().unwrap(), &mut rng);
+        let block_ids = block_ids.select(Axis(0), &order);
+        for level in nesting.iter_mut() {
+            level.ids = level.ids.select(Axis(0), &order);
+        }
+
+        // Simulate an observations x predictors design matrix, X.
+        let n_sites = mp.design.n_sites.get();
+        let mut x = Array::<S, _>::zeros((mp.n_obs.get(), mp.n_pred.get()));
+        // First column is the intercept.
+        x.column_mut(0).fill(S::one());
+        // Assign each block to a random site, and code the sites other than
+        // site zero with dummy variables in the next columns.
+        let site = Uniform::new(0, n_sites as u64);
+        let block_sites: Vec<usize> = (0..n_blocks).map(|_| site.sample(&mut rng) as usize).collect();
+        for (mut row, &block_id) in x.axis_iter_mut(Axis(0)).zip(block_ids.iter()) {
+            let site = block_sites[block_id];
+            if site > 0 {
+                row[site] = S::one();
+            }
+        }
+        // Remaining columns are continuous covariates, each the sum of a
+        // block effect and an observation effect scaled to give the desired
+        // correlation within blocks.
+        for mut column in x.slice_mut(s![.., n_sites..]).axis_iter_mut(Axis(1)) {
+            let icc = mp.design.covariate_icc.sample(&mut rng);
+            let effects: Vec<f64> = (0..n_blocks).map(|_| standard_normal(&mut rng)).collect();
+            for (x, &block_id) in column.iter_mut().zip(block_ids.iter()) {
+                let e = standard_normal(&mut rng);
+                *x = S::from((1. - icc).sqrt() * e + icc.sqrt() * effects[block_id]).unwrap();
+            }
+        }
+        // Predictors x observations pseudoinverse of X.
+        let x_pinv = pinv(x.view());
+
+        // Simulate the predictors x features matrix of true regression
+        // coefficients. They are drawn even if they are not kept, so that the
+        // same seed gives the same residuals either way.
+        let beta_sd = S::from(mp.design.beta_sd).unwrap();
+        let beta = Array::<S, _>::random_using((mp.n_pred.get(), mp.features.n_feat().get()), StandardNormal, &mut rng)
+            .mapv_into(|beta| beta * beta_sd);
+
+        // Standard deviation of the residuals for each observation, if the
+        // variance varies by block or by predictor.
+        let sd_obs: Option<Vec<S>> = match mp.variance {
+            VarianceStructure::ByBlock { log_sd } => {
+                let sd_block: Vec<f64> = (0..n_blocks)
+                    .map(|_| VarianceStructure::random_sd(log_sd, &mut rng))
+                    .collect();
+                Some(block_ids.iter().map(|&block_id| S::from(sd_block[block_id]).unwrap()).collect())
+            }
+            VarianceStructure::ByPredictor { pred, log_sd } => {
+                // Standardize the predictor, unless it is constant like the
+                // intercept.
+                let x = x.column(pred).mapv(|x| x.to_f64().unwrap());
+                let mean = x.mean().unwrap();
+                let sd = x.std(0.);
+                let sd = if sd > 0. { sd } else { 1. };
+                Some(x.iter().map(|x| S::from(VarianceStructure::sd(log_sd, (x - mean) / sd)).unwrap()).collect())
+            }
+            _ => None,
+        };
+
+        // Simulate the node scores, if the features are edges of a connectome.
+        let node_scores = match mp.features {
+            Features::Connectome { n_nodes, n_factors, shared } => {
+                Some((NodeScores::new(mp.n_obs.get(), n_nodes.get(), n_factors.get(), &mut rng), shared))
+            }
+            Features::Independent { .. } => None,
+        };
+
+        // Simulate an observations x features matrix of errors, ε. This is by
+        // far the largest array, so generate it in parallel, in chunks of
+        // features that each have their own random number stream.
+        let errors = mp.errors.sampler();
+        let mut resid = Array::<S, _>::zeros((mp.n_obs.get(), mp.features.n_feat().get()));
+        resid
+            .axis_chunks_iter_mut(Axis(1), CHUNK_FEAT)
+            .into_par_iter()
+            .enumerate()
+            .for_each(|(chunk, mut resid)| {
+                let mut rng = chunk_rng(seed, chunk);
+                // Start with iid errors from the error distribution.
+                for x in resid.iter_mut() {
+                    *x = S::from(errors.sample(&mut rng)).unwrap();
+                }
+                let n_feat = resid.len_of(Axis(1));
+                if let Some((node_scores, shared)) = &node_scores {
+                    // Mix in the scores of the nodes at either end of each
+                    // edge.
+                    let scale_error = (1. - shared).sqrt();
+                    let scale_nodes = shared.sqrt();
+                    for (j, mut column) in resid.axis_iter_mut(Axis(1)).enumerate() {
+                        let edge = chunk * CHUNK_FEAT + j;
+                        let (a, b) = node_scores.edges[edge];
+                        let norm = node_scores.norms[edge];
+                        for (x, scores) in column.iter_mut().zip(node_scores.scores.axis_iter(Axis(0))) {
+                            let nodes = (scores[a] + scores[b]) / norm;
+                            *x = S::from(x.to_f64().unwrap() * scale_error + nodes * scale_nodes).unwrap();
+                        }
+                    }
+                }
+                if !mp.icc.is_zero() {
+                    // Add a random intercept for each block, scaling the error
+                    // and the random effect to get the desired ICC for each
+                    // feature.
+                    let icc: Vec<f64> = (0..n_feat).map(|_| mp.icc.sample(&mut rng)).collect();
+                    let scale_error: Vec<S> = icc.iter().map(|icc| S::from((1. - icc).sqrt()).unwrap()).collect();
+                    let scale_effect: Vec<S> = icc.iter().map(|icc| S::from(icc.sqrt()).unwrap()).collect();
+                    let effects = Array::<S, _>::random_using((n_blocks, n_feat), StandardNormal, &mut rng);
+                    for (mut row, &block_id) in resid.axis_iter_mut(Axis(0)).zip(block_ids.iter()) {
+                        let effects = effects.row(block_id);
+                        for (j, x) in row.iter_mut().enumerate() {
+                            *x = *x * scale_error[j] + effects[j] * scale_effect[j];
+                        }
+                    }
+                }
+                // Scale the residuals for heteroskedasticity.
+                if let Some(sd_obs) = &sd_obs {
+                    for (mut row, &sd) in resid.axis_iter_mut(Axis(0)).zip(sd_obs.iter()) {
+                        row.mapv_inplace(|x| x * sd);
+                    }
+                }
+                if let VarianceStructure::ByFeature { log_sd } = mp.variance {
+                    for mut column in resid.axis_iter_mut(Axis(1)) {
+                        let sd = S::from(VarianceStructure::random_sd(log_sd, &mut rng)).unwrap();
+                        column.mapv_inplace(|x| x * sd);
+                    }
+                }
+            });
+
+        // Responses are Y = Xβ + ε, if they are kept.
+        let mut y = mp.design.keep_model.then(|| resid.clone());
+        if let Some(y) = &mut y {
+            y.axis_chunks_iter_mut(Axis(1), CHUNK_FEAT)
+                .into_par_iter()
+                .zip(beta.axis_chunks_iter(Axis(1), CHUNK_FEAT))
+                .for_each(|(y, beta)| mul_add_in_order(S::one(), x.view(), beta, y));
+        }
+        // Residuals of the least squares fit are ε - X * pinv(X) * ε, which
+        // we compute in place to avoid another copy of the largest array.
+        resid
+            .axis_chunks_iter_mut(Axis(1), CHUNK_FEAT)
+            .into_par_iter()
+            .for_each(|resid| {
+                let mut fitted = Array::<S, _>::zeros((x_pinv.nrows(), resid.ncols()));
+                mul_add_in_order(S::one(), x_pinv.view(), resid.view(), fitted.view_mut());
+                mul_add_in_order(-S::one(), x.view(), fitted.view(), resid);
+            });
+
+        Ok(Self {
+            // Ids are dense by construction.
+            block_ids: BlockIds::new(block_ids).unwrap(),
+            block_labels: None,
             x_pinv,
             resid,
-        }
+            x: mp.design.keep_model.then_some(x),
+            y,
+            beta: mp.design.keep_model.then_some(beta),
+            nesting,
+            params: Some(params),
+        })
     }
 }
 impl <S> MockData<S>
 where
-    S: Clone + WritableElement,
+    S: Clone + WritableElement + MatElement + H5Element,
 {
-    /// Save to a file.
+    /// Save to a file without compression, see
+    /// [`save_npz_file_with()`](MockData::save_npz_file_with).
+    ///
+    /// The npz file always contains the arrays `schema_version` (see
+    /// [`NPZ_SCHEMA_VERSION`]), `n_blocks`, `block_ids`, `x_pinv` and `resid`.
+    /// The optional members are only written if they are known: `x`, `y` and
+    /// `beta`; `block_labels` and `block_label_offsets`; `nesting_ids` and
+    /// `nesting_shuffle`, 1 for levels shuffled as whole blocks and 0 for
+    /// levels shuffled within blocks; and `params`, the UTF-8 bytes of the
+    /// informational description of the generation parameters.
     /// 
     /// Example:
     /// ```no_run
     /// use std::fs::File;
-    /// let mock_data = MockData::from_params(MockParams::default());
+    /// # use swe_mockup::{MockData, MockParams};
+    /// let mock_data = MockData::<f64>::from_params(MockParams::default());
     /// mock_data.save_npz_file(File::create("mock-data.npz")?)?;
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    pub fn save_npz_file<W: Seek + Write>(&self, w: W) -> Result<(), Error> {
+        self.save_npz_file_with(w, Compression::Stored)
+    }
+
+    /// Save to a file with a choice of compression. Returns an error, before
+    /// writing anything, if the compression is not valid, see
+    /// [`Compression::is_valid()`].
+    ///
+    /// Example:
+    /// ```no_run
+    /// use std::fs::File;
+    /// # use swe_mockup::{MockData, MockParams};
+    /// use swe_mockup::npz::Compression;
+    /// let mock_data = MockData::<f64>::from_params(MockParams::default());
+    /// mock_data.save_npz_file_with(File::create("mock-data.npz")?, Compression::Deflate { level: 1 })?;
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    pub fn save_npz_file_with<W: Seek + Write>(&self, w: W, compression: Compression) -> Result<(), Error> {
+        let mut npz = NpzWriter::new(w, compression)?;
+        self.save(&mut npz)?;
+        npz.finish()
+    }
+
+    /// Save to a directory with one npy file per array, e.g. for MATLAB's
+    /// `readNPY()`. The directory is created if it does not exist. The files
+    /// are named after the arrays in [`save_npz_file()`](MockData::save_npz_file)
+    /// with a `.npy` suffix, e.g. `x_pinv.npy`. Any npy files of those names
+    /// already in the directory are removed first, so that optional arrays
+    /// from earlier mock data are not mixed with these; other files are left
+    /// alone.
+    ///
+    /// Example:
+    /// ```no_run
+    /// # use swe_mockup::{MockData, MockParams};
+    /// let mock_data = MockData::<f64>::from_params(MockParams::default());
+    /// mock_data.save_npy_dir("mock-data")?;
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
     /// ```
-    pub fn save_npz_file<W: Seek + Write>(&self, w: W) -> Result<(), WriteNpzError> {
+    pub fn save_npy_dir<P: AsRef<Path>>(&self, dir: P) -> Result<(), Error> {
+        let mut npy_dir = NpyDir::create(dir.as_ref())?;
+        npy_dir.remove(NPZ_ARRAYS)?;
+        self.save(&mut npy_dir)
+    }
+
+    /// Save to a MATLAB Level 5 MAT-file, which MATLAB can `load` without
+    /// extra tooling. The variables are named after the arrays in
+    /// [`save_npz_file()`](MockData::save_npz_file). Returns an error, before
+    /// writing anything, if any array is too large for MATLAB to load from a
+    /// Level 5 MAT-file.
+    ///
+    /// Example:
+    /// ```no_run
+    /// use std::fs::File;
+    /// # use swe_mockup::{MockData, MockParams};
+    /// let mock_data = MockData::<f64>::from_params(MockParams::default());
+    /// mock_data.save_mat_file(File::create("mock-data.mat")?)?;
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    pub fn save_mat_file<W: Write>(&self, w: W) -> Result<(), Error> {
+        self.save(&mut MatSizes)?;
+        let mut mat = MatWriter::new(w)?;
+        self.save(&mut mat)?;
+        mat.finish()?;
+        Ok(())
+    }
+
+    /// Save to an HDF5 file with a choice of compression, with the `hdf5`
+    /// feature. The datasets are named after the arrays in
+    /// [`save_npz_file()`](MockData::save_npz_file) and chunked along their
+    /// last axis, see [`h5`]. Returns an error if the compression is not
+    /// valid.
+    ///
+    /// Example:
+    /// ```no_run
+    /// # use swe_mockup::{MockData, MockParams};
+    /// use swe_mockup::npz::Compression;
+    /// let mock_data = MockData::<f64>::from_params(MockParams::default());
+    /// mock_data.save_hdf5_file("mock-data.h5", Compression::Deflate { level: 4 })?;
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    #[cfg(feature = "hdf5")]
+    pub fn save_hdf5_file<P: AsRef<Path>>(&self, path: P, compression: Compression) -> Result<(), Error> {
+        let mut h5 = H5File::create(path, compression)?;
+        self.save(&mut h5)?;
+        h5.close()
+    }
+
+    // Save the arrays to an npz file, a directory of npy files, a MAT-file or
+    // an HDF5 file.
+    fn save<A: ArraySink>(&self, npz: &mut A) -> Result<(), Error> {
         // Destructure self to make sure we handle all fields.
         let Self {
-            n_blocks,
             block_ids,
+            block_labels,
             x_pinv,
             resid,
+            x,
+            y,
+            beta,
+            nesting,
+            params,
         } = self;
 
         // Convert usize to u64.
-        let block_ids = block_ids.mapv(|x| x as u64);
+        let n_blocks = block_ids.n_blocks();
+        let block_ids = block_ids.view().mapv(|x| x as u64);
 
         // Put number of blocks in an array.
         let n_blocks = Array::<u64,_>::from_elem((1,), n_blocks.get() as u64);
 
-        // Initialize npz file.
-        let mut npz = NpzWriter::new(w);
-
         // Add data to file.
+        let schema_version = Array::from(vec![NPZ_SCHEMA_VERSION.0, NPZ_SCHEMA_VERSION.1]);
+        npz.add_array("schema_version", &schema_version)?;
         npz.add_array("n_blocks", &n_blocks)?;
         npz.add_array("block_ids", &block_ids)?;
         npz.add_array("x_pinv", x_pinv)?;
         npz.add_array("resid", resid)?;
 
-        // Finish zip headers and flush file.
-        npz.finish()?;
+        // Add the block labels, if any, as their concatenated UTF-8 bytes and
+        // the offset at which each label starts, followed by the total length.
+        if let Some(block_labels) = block_labels {
+            let mut bytes = Vec::new();
+            let mut offsets = vec![0u64];
+            for label in block_labels.labels() {
+                bytes.extend_from_slice(label.as_bytes());
+                offsets.push(bytes.len() as u64);
+            }
+            npz.add_array("block_labels", &Array::from(bytes))?;
+            npz.add_array("block_label_offsets", &Array::from(offsets))?;
+        }
+
+        // Add the design matrix, responses and true coefficients, if known.
+        if let Some(x) = x {
+            npz.add_array("x", x)?;
+        }
+        if let Some(y) = y {
+            npz.add_array("y", y)?;
+        }
+        if let Some(beta) = beta {
+            npz.add_array("beta", beta)?;
+        }
+
+        // Add enclosing levels of blocks, if any, as a levels x observations
+        // matrix of ids and a vector of shuffle flags (1 for whole-block).
+        if !nesting.is_empty() {
+            let mut nesting_ids = Array::<u64, _>::zeros((nesting.len(), block_ids.len()));
+            for (mut row, level) in nesting_ids.axis_iter_mut(Axis(0)).zip(nesting.iter()) {
+                row.assign(&level.ids.mapv(|x| x as u64));
+            }
+            let nesting_shuffle: Array<u8, _> = nesting
+                .iter()
+                .map(|level| (level.shuffle == Shuffle::WholeBlock) as u8)
+                .collect();
+            npz.add_array("nesting_ids", &nesting_ids)?;
+            npz.add_array("nesting_shuffle", &nesting_shuffle)?;
+        }
+
+        // Add the description of the generation parameters, if known.
+        if let Some(params) = params {
+            let params = Array::from(params.as_bytes().to_vec());
+            npz.add_array("params", &params)?;
+        }
 
         // All done.
         Ok(())
@@ -237,43 +1506,447 @@ where
 }
 impl <S> MockData<S>
 where
-    S: Clone + ReadableElement,
+    S: num_traits::Float + ReadableElement + MatElement + H5Element,
 {
     /// Load from a file.
     /// 
-    /// Warning: Does not validate data in file and may panic if data is
-    /// invalid.
+    /// The data are validated: the shapes of the arrays must agree with each
+    /// other, the block ids must be dense and agree with the number of
+    /// blocks, and every value must be finite. Files written by earlier
+    /// versions of this crate, where block ids start at one, are converted
+    /// with [`BlockIds::from_old_layout()`]. Optional arrays missing from the
+    /// file are `None`, and unknown arrays are ignored. Files with a newer
+    /// major version of the schema than [`NPZ_SCHEMA_VERSION`] are rejected.
+    ///
+    /// Files written by numpy or MATLAB are accepted too: arrays may be named
+    /// with or without a `.npy` suffix, floating point arrays may be f32 or f64
+    /// and are converted to S, ids may have any integer type and are checked
+    /// to fit in a usize, and arrays may be in C or Fortran order. Fortran
+    /// ordered arrays of type S are used as is without copying.
     /// 
     /// Example:
     /// ```no_run
     /// use std::fs::File;
-    /// let mock_data = MockData::from_npz_file(File::open("mock-data.npz")?)?;
+    /// # use swe_mockup::MockData;
+    /// let mock_data = MockData::<f64>::from_npz_file(File::open("mock-data.npz")?)?;
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
     /// ```
-    pub fn from_npz_file<R: Seek + Read>(r: R) -> Result<Self, ReadNpzError> {
-        // Read the header of the npz file.
-        let mut npz = NpzReader::new(r)?;
+    pub fn from_npz_file<R: Seek + Read>(r: R) -> Result<Self, Error> {
+        Self::load(&mut NpzSource::new(r)?)
+    }
+
+    /// Load from a directory with one npy file per array, as written by
+    /// [`save_npy_dir()`](MockData::save_npy_dir). The data are validated and
+    /// converted in the same way as by
+    /// [`from_npz_file()`](MockData::from_npz_file).
+    ///
+    /// Example:
+    /// ```no_run
+    /// # use swe_mockup::MockData;
+    /// let mock_data = MockData::<f64>::from_npy_dir("mock-data")?;
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    pub fn from_npy_dir<P: AsRef<Path>>(dir: P) -> Result<Self, Error> {
+        Self::load(&mut NpyDir::open(dir.as_ref())?)
+    }
+
+    /// Load from a MATLAB Level 5 MAT-file, e.g. as written by
+    /// [`save_mat_file()`](MockData::save_mat_file) or saved by MATLAB. The
+    /// data are validated and converted in the same way as by
+    /// [`from_npz_file()`](MockData::from_npz_file), and ids may also be
+    /// stored as doubles with whole number values, MATLAB's default.
+    ///
+    /// Example:
+    /// ```no_run
+    /// use std::fs::File;
+    /// # use swe_mockup::MockData;
+    /// let mock_data = MockData::<f64>::from_mat_file(File::open("mock-data.mat")?)?;
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    pub fn from_mat_file<R: Read>(r: R) -> Result<Self, Error> {
+        Self::load(&mut MatReader::new(r)?)
+    }
+
+    /// Load from an HDF5 file, e.g. as written by
+    /// [`save_hdf5_file()`](MockData::save_hdf5_file), with the `hdf5`
+    /// feature. The data are validated and converted in the same way as by
+    /// [`from_npz_file()`](MockData::from_npz_file).
+    ///
+    /// Example:
+    /// ```no_run
+    /// # use swe_mockup::MockData;
+    /// let mock_data = MockData::<f64>::from_hdf5_file("mock-data.h5")?;
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    #[cfg(feature = "hdf5")]
+    pub fn from_hdf5_file<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        Self::load(&mut H5File::open(path)?)
+    }
+
+    /// Load a range of features from an HDF5 file, with the `hdf5` feature.
+    /// Only those columns of `resid`, `y` and `beta` are read, so that data
+    /// too large for memory can be processed a chunk of features at a time,
+    /// see [`h5`]. The other arrays are read whole. Returns an error if the
+    /// range is empty or extends past the last feature.
+    #[cfg(feature = "hdf5")]
+    pub fn from_hdf5_features<P: AsRef<Path>>(path: P, features: std::ops::Range<usize>) -> Result<Self, Error> {
+        let file = H5File::open(path)?;
+        let n_feat = file.shape("resid")?.last().copied().unwrap_or(0);
+        if features.is_empty() || features.end > n_feat {
+            return Err(Error::Argument(format!(
+                "Features {}..{} must be a non-empty range within the {} features.",
+                features.start, features.end, n_feat
+            )));
+        }
+        Self::load(&mut FeatureSlab {
+            file: &file,
+            arrays: &["resid", "y", "beta"],
+            features,
+        })
+    }
+
+    // Load the arrays from an npz file, a directory of npy files, a MAT-file
+    // or an HDF5 file.
+    fn load<A: ArraySource>(npz: &mut A) -> Result<Self, Error> {
+        // Check the version of the schema. Files without a version predate
+        // versioning and have version 1.0.
+        if npz.has("schema_version") {
+            let version: Array<usize, Dim<[usize; 1]>> = read_ids(npz, "schema_version")?;
+            check_shape("schema_version", version.shape(), &[2])?;
+            let (major, minor) = (version[0] as u64, version[1] as u64);
+            if major > NPZ_SCHEMA_VERSION.0 {
+                return Err(MockDataError::SchemaVersion { major, minor }.into());
+            }
+        }
 
         // Read data from file.
-        let n_blocks: Array<u64, Dim<[usize; 1]>> = npz.by_name("n_blocks")?;
-        let block_ids: Array<u64, Dim<[usize; 1]>> = npz.by_name("block_ids")?;
-        let x_pinv: Array<S, Dim<[usize; 2]>> = npz.by_name("x_pinv")?;
-        let resid: Array<S, Dim<[usize; 2]>> = npz.by_name("resid")?;
+        let n_blocks: Array<usize, Dim<[usize; 1]>> = read_ids(npz, "n_blocks")?;
+        let block_ids: Array<usize, Dim<[usize; 1]>> = read_ids(npz, "block_ids")?;
+        let x_pinv: Array<S, Dim<[usize; 2]>> = read_float(npz, "x_pinv")?;
+        let resid: Array<S, Dim<[usize; 2]>> = read_float(npz, "resid")?;
+
+        // Check the shapes of the arrays against the number of observations
+        // in block_ids.
+        let n_obs = block_ids.len();
+        let (n_pred, n_feat) = (x_pinv.nrows(), resid.ncols());
+        check_shape("n_blocks", n_blocks.shape(), &[1])?;
+        check_shape("x_pinv", x_pinv.shape(), &[n_pred, n_obs])?;
+        check_shape("resid", resid.shape(), &[n_obs, n_feat])?;
+        if n_pred == 0 {
+            return Err(MockDataError::Invalid("Number of predictors, the rows of x_pinv, must be non-zero.".to_string()).into());
+        }
+        if n_feat == 0 {
+            return Err(MockDataError::Invalid("Number of features, the columns of resid, must be non-zero.".to_string()).into());
+        }
+
+        // Extract number of blocks from array. Files written by earlier
+        // versions have ids from one up to and including n_blocks, plus zero
+        // for a trailing partial block, so that the largest id is n_blocks
+        // rather than n_blocks - 1. Convert these to dense ids.
+        let n_blocks = n_blocks[0];
+        if n_blocks == 0 {
+            return Err(MockDataError::Invalid("Number of blocks must be non-zero.".to_string()).into());
+        }
+        // Check the ids against the number of blocks before building
+        // BlockIds, which allocates a flag per block, so that a corrupt id or
+        // number of blocks is an error rather than a huge allocation. Every
+        // block but the partial block of the old layout has an observation.
+        if n_blocks > n_obs {
+            let msg = format!("File has {} blocks but only {} observations.", n_blocks, n_obs);
+            return Err(MockDataError::Invalid(msg).into());
+        }
+        if let Some(&block_id) = block_ids.iter().find(|&&block_id| block_id > n_blocks) {
+            let err = blocks::BlockIdsError::OutOfRange { block_id, n_blocks };
+            return Err(MockDataError::from(err).into());
+        }
+        let block_ids = if block_ids.iter().max() == Some(&n_blocks) {
+            BlockIds::from_old_layout(block_ids, n_blocks).map_err(MockDataError::from)?
+        } else {
+            let block_ids = BlockIds::new(block_ids).map_err(MockDataError::from)?;
+            if block_ids.n_blocks().get() != n_blocks {
+                return Err(MockDataError::NBlocks { n_blocks, n_ids: block_ids.n_blocks().get() }.into());
+            }
+            block_ids
+        };
 
-        // Convert u64 to usize.
-        let block_ids = block_ids.mapv_into_any(|x: u64| x as usize);
+        // Read the block labels, if any.
+        let block_labels = if npz.has("block_labels") {
+            let bytes: Array<u8, Dim<[usize; 1]>> = read_bytes(npz, "block_labels")?;
+            let offsets: Array<usize, Dim<[usize; 1]>> = read_ids(npz, "block_label_offsets")?;
+            check_shape("block_label_offsets", offsets.shape(), &[block_ids.n_blocks().get() + 1])?;
+            let bytes = bytes.as_slice().unwrap();
+            let labels = offsets
+                .windows(2)
+                .into_iter()
+                .map(|offsets| {
+                    bytes
+                        .get(offsets[0]..offsets[1])
+                        .and_then(|label| String::from_utf8(label.to_vec()).ok())
+                        .ok_or_else(|| MockDataError::Invalid("Block labels are not valid UTF-8 strings.".to_string()))
+                })
+                .collect::<Result<_, _>>()?;
+            Some(
+                BlockLabels::new(labels)
+                    .ok_or_else(|| MockDataError::Invalid("Block labels are not distinct.".to_string()))?,
+            )
+        } else {
+            None
+        };
 
-        // Extract number of blocks from array.
-        let n_blocks = NonZeroUsize::new(n_blocks[0 as usize] as usize).unwrap(); // TODO error handling
+        // Read the design matrix, responses and true coefficients, which are
+        // only present in the file if they are known.
+        let x: Option<Array<S, Dim<[usize; 2]>>> = if npz.has("x") { Some(read_float(npz, "x")?) } else { None };
+        let y: Option<Array<S, Dim<[usize; 2]>>> = if npz.has("y") { Some(read_float(npz, "y")?) } else { None };
+        let beta: Option<Array<S, Dim<[usize; 2]>>> = if npz.has("beta") { Some(read_float(npz, "beta")?) } else { None };
+        if let Some(x) = &x {
+            check_shape("x", x.shape(), &[n_obs, n_pred])?;
+        }
+        if let Some(y) = &y {
+            check_shape("y", y.shape(), &[n_obs, n_feat])?;
+        }
+        if let Some(beta) = &beta {
+            check_shape("beta", beta.shape(), &[n_pred, n_feat])?;
+        }
+
+        // Every value must be finite.
+        check_finite("x_pinv", x_pinv.view())?;
+        check_finite("resid", resid.view())?;
+        for (name, array) in [("x", &x), ("y", &y), ("beta", &beta)] {
+            if let Some(array) = array {
+                check_finite(name, array.view())?;
+            }
+        }
+
+        // Read enclosing levels of blocks, which are only present in the file
+        // if there are any.
+        let mut nesting = Vec::new();
+        if npz.has("nesting_ids") {
+            let nesting_ids: Array<usize, Dim<[usize; 2]>> = read_ids(npz, "nesting_ids")?;
+            let nesting_shuffle: Array<usize, Dim<[usize; 1]>> = read_ids(npz, "nesting_shuffle")?;
+            let n_levels = nesting_shuffle.len();
+            check_shape("nesting_ids", nesting_ids.shape(), &[n_levels, n_obs])?;
+            for (ids, &shuffle) in nesting_ids.axis_iter(Axis(0)).zip(nesting_shuffle.iter()) {
+                let shuffle = match shuffle {
+                    0 => Shuffle::WithinBlock,
+                    1 => Shuffle::WholeBlock,
+                    _ => {
+                        let msg = format!("Shuffle {} of an enclosing level of blocks is neither 0 nor 1.", shuffle);
+                        return Err(MockDataError::Invalid(msg).into());
+                    }
+                };
+                nesting.push(BlockLevel { ids: ids.to_owned(), shuffle });
+            }
+        }
+
+        // Read the description of the generation parameters, if known.
+        let params = if npz.has("params") {
+            let bytes: Array<u8, Dim<[usize; 1]>> = read_bytes(npz, "params")?;
+            Some(
+                String::from_utf8(bytes.to_vec())
+                    .map_err(|_| MockDataError::Invalid("Parameters are not a valid UTF-8 string.".to_string()))?,
+            )
+        } else {
+            None
+        };
 
         // Construct self.
-        Ok(Self{
-            n_blocks,
+        let mock_data = Self {
             block_ids,
+            block_labels,
             x_pinv,
             resid,
+            x,
+            y,
+            beta,
+            nesting,
+            params,
+        };
+
+        // The levels of blocks must be nested.
+        ExchangeabilityTree::new(mock_data.levels(Shuffle::default())).map_err(MockDataError::from)?;
+
+        Ok(mock_data)
+    }
+}
+
+// Check the shape of an array read from an npz file.
+fn check_shape(name: &'static str, found: &[usize], expected: &[usize]) -> Result<(), MockDataError> {
+    if found == expected {
+        Ok(())
+    } else {
+        Err(MockDataError::Shape {
+            name,
+            expected: expected.to_vec(),
+            found: found.to_vec(),
         })
     }
 }
+
+// Check that every value of an array read from an npz file is finite.
+fn check_finite<S, D>(name: &'static str, array: ArrayView<S, D>) -> Result<(), MockDataError>
+where
+    S: num_traits::Float,
+    D: ndarray::Dimension,
+{
+    if array.iter().all(|x| x.is_finite()) {
+        Ok(())
+    } else {
+        Err(MockDataError::NotFinite { name })
+    }
+}
+
+// Read an array of floating point numbers. Arrays of f32 or f64 are
+// converted to S if the file has a different type, e.g. single precision data
+// written by MATLAB. Arrays in Fortran order keep their memory order rather
+// than being copied into C order.
+fn read_float<S, A, D>(npz: &mut A, name: &'static str) -> Result<Array<S, D>, Error>
+where
+    S: num_traits::Float + ReadableElement + MatElement + H5Element,
+    A: ArraySource,
+    D: ndarray::Dimension,
+{
+    if let Some(array) = npz.read(name)? {
+        return Ok(array);
+    }
+    // Values too large for S become infinite and are rejected later.
+    if let Some(array) = npz.read::<f64, D>(name)? {
+        return Ok(array.mapv(|x| S::from(x).unwrap_or_else(S::infinity)));
+    }
+    if let Some(array) = npz.read::<f32, D>(name)? {
+        return Ok(array.mapv(|x| S::from(x).unwrap_or_else(S::infinity)));
+    }
+    Err(MockDataError::Type { name, expected: "floating point" }.into())
+}
+
+// Read an array of ids. The file may have any integer type, e.g. int32 from
+// MATLAB or int64 from numpy. Every id is checked to be non-negative and to
+// fit in a usize on this platform.
+fn read_ids<A, D>(npz: &mut A, name: &'static str) -> Result<Array<usize, D>, Error>
+where
+    A: ArraySource,
+    D: ndarray::Dimension,
+{
+    // Try each integer type in turn until one matches the file.
+    macro_rules! try_read {
+        ($($t:ty),*) => {$(
+            if let Some(ids) = npz.read::<$t, D>(name)? {
+                if let Some(&value) = ids.iter().find(|&&id| usize::try_from(id).is_err()) {
+                    return Err(MockDataError::Overflow { name, value: i128::from(value) }.into());
+                }
+                return Ok(ids.mapv(|id| usize::try_from(id).unwrap()));
+            }
+        )*};
+    }
+    try_read!(u64, i64, u32, i32, u16, i16, u8, i8);
+    Err(MockDataError::Type { name, expected: "integer" }.into())
+}
+
+// Read an array of bytes, e.g. UTF-8 strings.
+fn read_bytes<A: ArraySource>(npz: &mut A, name: &'static str) -> Result<Array<u8, Dim<[usize; 1]>>, Error> {
+    npz.read(name)?
+        .ok_or_else(|| MockDataError::Type { name, expected: "uint8" }.into())
+}
+
+/// Mock data read from a file are not valid.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum MockDataError {
+    /// An array does not have the expected shape.
+    Shape {
+        /// Name of the array
+        name: &'static str,
+        /// Expected shape
+        expected: Vec<usize>,
+        /// Shape in the file
+        found: Vec<usize>,
+    },
+    /// An id is negative or too large to be represented on this platform.
+    Overflow {
+        /// Name of the array
+        name: &'static str,
+        /// The offending value
+        value: i128,
+    },
+    /// The block ids are not valid.
+    BlockIds(blocks::BlockIdsError),
+    /// The number of blocks in the file does not agree with the block ids.
+    NBlocks {
+        /// Number of blocks in the file
+        n_blocks: usize,
+        /// Number of blocks according to the block ids
+        n_ids: usize,
+    },
+    /// An array does not have the expected type of elements.
+    Type {
+        /// Name of the array
+        name: &'static str,
+        /// Expected type of elements
+        expected: &'static str,
+    },
+    /// An array contains values which are not finite.
+    NotFinite {
+        /// Name of the array
+        name: &'static str,
+    },
+    /// The enclosing levels of blocks are not nested.
+    Nesting(exchange::ExchangeabilityError),
+    /// The file was written with a newer, incompatible version of the
+    /// schema.
+    SchemaVersion {
+        /// Major version in the file
+        major: u64,
+        /// Minor version in the file
+        minor: u64,
+    },
+    /// Some other part of the file is invalid.
+    Invalid(String),
+}
+impl std::fmt::Display for MockDataError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            MockDataError::Shape { name, expected, found } => {
+                write!(f, "Array {} has shape {:?} but expected {:?}.", name, found, expected)
+            }
+            MockDataError::Overflow { name, value } => {
+                write!(f, "Value {} in array {} is not a valid id on this platform.", value, name)
+            }
+            MockDataError::BlockIds(err) => write!(f, "Invalid block ids: {}", err),
+            MockDataError::NBlocks { n_blocks, n_ids } => write!(
+                f,
+                "File has {} blocks but the block ids have {} blocks.",
+                n_blocks, n_ids
+            ),
+            MockDataError::Type { name, expected } => write!(f, "Array {} does not have elements of {} type.", name, expected),
+            MockDataError::NotFinite { name } => write!(f, "Array {} has values which are not finite.", name),
+            MockDataError::Nesting(err) => write!(f, "Invalid levels of blocks: {}", err),
+            MockDataError::SchemaVersion { major, minor } => write!(
+                f,
+                "File has schema version {}.{} but only versions up to {}.x are supported.",
+                major, minor, NPZ_SCHEMA_VERSION.0
+            ),
+            MockDataError::Invalid(msg) => write!(f, "Invalid mock data: {}", msg),
+        }
+    }
+}
+impl std::error::Error for MockDataError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            MockDataError::BlockIds(err) => Some(err),
+            MockDataError::Nesting(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+impl From<blocks::BlockIdsError> for MockDataError {
+    fn from(err: blocks::BlockIdsError) -> Self {
+        MockDataError::BlockIds(err)
+    }
+}
+impl From<exchange::ExchangeabilityError> for MockDataError {
+    fn from(err: exchange::ExchangeabilityError) -> Self {
+        MockDataError::Nesting(err)
+    }
+}
 impl <S> MockData<S>
 where
     S: Clone,
@@ -288,10 +1961,36 @@ where
         NonZeroUsize::new(self.x_pinv.shape()[0]).unwrap()
     }
 
+    /// Label of a block id for reporting, which is the original label if the
+    /// blocks are labeled and otherwise the id itself.
+    pub fn block_label(&self, block_id: usize) -> String {
+        self.block_labels
+            .as_ref()
+            .and_then(|block_labels| block_labels.label(block_id).cloned())
+            .unwrap_or_else(|| block_id.to_string())
+    }
+
     /// Number of observations.
     pub fn n_obs(&self) -> NonZeroUsize {
         NonZeroUsize::new(self.resid.shape()[0]).unwrap()
     }
+
+    /// Exchangeability tree of the enclosing levels of blocks followed by the
+    /// blocks themselves, which are resampled according to `block_shuffle`.
+    pub fn exchangeability_tree(&self, block_shuffle: Shuffle) -> ExchangeabilityTree {
+        // Levels are nested by construction or validated when loaded.
+        ExchangeabilityTree::new(self.levels(block_shuffle)).unwrap()
+    }
+
+    // Enclosing levels of blocks followed by the blocks themselves.
+    fn levels(&self, block_shuffle: Shuffle) -> Vec<BlockLevel> {
+        let mut levels = self.nesting.clone();
+        levels.push(BlockLevel {
+            ids: self.block_ids.view().to_owned(),
+            shuffle: block_shuffle,
+        });
+        levels
+    }
 }
 
 impl <S: Clone> std::fmt::Display for MockData<S> {
@@ -300,7 +1999,295 @@ impl <S: Clone> std::fmt::Display for MockData<S> {
         writeln!(f, "Number of observations: {}", self.n_obs())?;
         writeln!(f, "Number of features: {}", self.n_feat())?;
         writeln!(f, "Number of predictors: {}", self.n_pred())?;
-        writeln!(f, "Number of blocks: {}", self.n_blocks)?;
+        writeln!(f, "Number of blocks: {}", self.block_ids.n_blocks())?;
+        for (level, nesting) in self.nesting.iter().enumerate() {
+            let n_blocks = nesting.ids.iter().collect::<std::collections::HashSet<_>>().len();
+            writeln!(f, "Number of enclosing blocks at level {}: {}", level, n_blocks)?;
+        }
         Ok(())
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Check the Moore-Penrose conditions X * P * X = X and P * X * P = P.
+    fn assert_pinv(x: &Array<f64, Dim<[usize; 2]>>, p: &Array<f64, Dim<[usize; 2]>>) {
+        let xpx = x.dot(p).dot(x);
+        let pxp = p.dot(x).dot(p);
+        assert!(xpx.abs_diff_eq(x, 1e-10), "X * P * X != X");
+        assert!(pxp.abs_diff_eq(p, 1e-10), "P * X * P != P");
+    }
+
+    #[test]
+    fn pinv_of_full_rank_design() {
+        let x = Array::from_shape_fn((20, 4), |(obs, pred)| {
+            let obs = obs as f64;
+            [1., obs.sin(), obs * obs / 100., (3. * obs).cos()][pred]
+        });
+        let p = pinv(x.view());
+        assert_eq!(p.dim(), (4, 20));
+        assert_pinv(&x, &p);
+        assert!(p.dot(&x).abs_diff_eq(&Array::eye(4), 1e-10));
+    }
+
+    #[test]
+    fn pinv_of_rank_deficient_design() {
+        // A site dummy with no observations and a site dummy equal to the
+        // intercept.
+        let x = Array::from_shape_fn((12, 4), |(obs, pred)| match pred {
+            0 | 2 => 1.,
+            1 => 0.,
+            _ => (obs as f64).cos(),
+        });
+        let p = pinv(x.view());
+        assert_pinv(&x, &p);
+        assert!(p.row(1).iter().all(|&p| p == 0.));
+    }
+
+    // Small mock data with several chunks of features, correlated residuals
+    // and a connectome, generated in a pool of `n_threads` threads.
+    fn small_mock_data(n_threads: usize) -> MockData<f64> {
+        let mut params = MockParams {
+            n_obs: NonZeroUsize::new(90).unwrap(),
+            features: Features::Connectome {
+                n_nodes: NonZeroUsize::new(20).unwrap(),
+                n_factors: NonZeroUsize::new(3).unwrap(),
+                shared: 0.3,
+            },
+            n_pred: NonZeroUsize::new(4).unwrap(),
+            icc: Icc::uniform((0.1, 0.5)).unwrap(),
+            seed: Some(42),
+            ..MockParams::default()
+        };
+        params.design.n_sites = NonZeroUsize::new(2).unwrap();
+        let pool = rayon::ThreadPoolBuilder::new().num_threads(n_threads).build().unwrap();
+        pool.install(|| MockData::from_params(params))
+    }
+
+    // Bit patterns of the elements of an array, so that comparisons are exact
+    // even for NaN.
+    fn bits<D: ndarray::Dimension>(array: &Array<f64, D>) -> Vec<u64> {
+        array.iter().map(|x| x.to_bits()).collect()
+    }
+
+    #[test]
+    fn same_seed_gives_identical_data_with_any_number_of_threads() {
+        let one = small_mock_data(1);
+        let again = small_mock_data(1);
+        let four = small_mock_data(4);
+        assert!(one.n_feat().get() > CHUNK_FEAT);
+        for other in [&again, &four] {
+            assert_eq!(other.block_ids, one.block_ids);
+            assert_eq!(bits(&other.x_pinv), bits(&one.x_pinv));
+            assert_eq!(bits(&other.resid), bits(&one.resid));
+        }
+    }
+
+    type RawNpz = ndarray_npy::NpzWriter<std::io::Cursor<Vec<u8>>>;
+
+    // Load mock data from an npz file of 4 observations in 2 blocks, 1
+    // predictor and 2 features. The arrays named in `replaced` are left out
+    // and `write` adds replacements or further arrays.
+    fn load_raw_npz<F: FnOnce(&mut RawNpz)>(replaced: &[&str], write: F) -> Result<MockData<f64>, Error> {
+        let mut npz = RawNpz::new(std::io::Cursor::new(Vec::new()));
+        if !replaced.contains(&"n_blocks") {
+            npz.add_array("n_blocks", &Array::from(vec![2u64])).unwrap();
+        }
+        if !replaced.contains(&"block_ids") {
+            npz.add_array("block_ids", &Array::from(vec![0u64, 0, 1, 1])).unwrap();
+        }
+        if !replaced.contains(&"x_pinv") {
+            npz.add_array("x_pinv", &Array::from_elem((1, 4), 0.25)).unwrap();
+        }
+        if !replaced.contains(&"resid") {
+            npz.add_array("resid", &Array::from_shape_vec((4, 2), vec![1., -1., -1., 1., 0.5, 0., -0.5, 0.]).unwrap()).unwrap();
+        }
+        write(&mut npz);
+        let cursor = npz.finish().unwrap();
+        MockData::from_npz_file(std::io::Cursor::new(cursor.into_inner()))
+    }
+
+    // Load a corrupt npz file, see `load_raw_npz()`, which must fail.
+    fn load_corrupt_npz<F: FnOnce(&mut RawNpz)>(replaced: &[&str], write: F) -> MockDataError {
+        match load_raw_npz(replaced, write) {
+            Ok(_) => panic!("corrupt file was loaded"),
+            Err(Error::MockData(err)) => err,
+            Err(err) => panic!("unexpected error: {}", err),
+        }
+    }
+
+    #[test]
+    fn load_accepts_valid_nesting() {
+        let mock_data = load_raw_npz(&[], |npz| {
+            npz.add_array("nesting_ids", &Array::from_shape_vec((2, 4), vec![0u64, 0, 0, 0, 0, 0, 1, 1]).unwrap()).unwrap();
+            npz.add_array("nesting_shuffle", &Array::from(vec![0u8, 1])).unwrap();
+        })
+        .unwrap();
+        let shuffles: Vec<_> = mock_data.nesting.iter().map(|level| level.shuffle).collect();
+        assert_eq!(shuffles, vec![Shuffle::WithinBlock, Shuffle::WholeBlock]);
+    }
+
+    #[test]
+    fn load_rejects_unknown_nesting_shuffle() {
+        let err = load_corrupt_npz(&[], |npz| {
+            npz.add_array("nesting_ids", &Array::from_shape_vec((1, 4), vec![0u64; 4]).unwrap()).unwrap();
+            npz.add_array("nesting_shuffle", &Array::from(vec![7u8])).unwrap();
+        });
+        assert!(matches!(err, MockDataError::Invalid(_)), "{:?}", err);
+    }
+
+    #[test]
+    fn load_accepts_valid_npz() {
+        let mock_data = load_raw_npz(&[], |_| ()).unwrap();
+        assert_eq!(mock_data.block_ids.view().to_vec(), vec![0, 0, 1, 1]);
+        assert_eq!(mock_data.resid.dim(), (4, 2));
+    }
+
+    #[test]
+    fn load_rejects_wrong_shape() {
+        let err = load_corrupt_npz(&["x_pinv"], |npz| {
+            npz.add_array("x_pinv", &Array::from_elem((1, 3), 0.25)).unwrap();
+        });
+        match err {
+            MockDataError::Shape { name, expected, found } => {
+                assert_eq!(name, "x_pinv");
+                assert_eq!(expected, vec![1, 4]);
+                assert_eq!(found, vec![1, 3]);
+            }
+            err => panic!("unexpected error: {:?}", err),
+        }
+    }
+
+    #[test]
+    fn load_rejects_ids_out_of_range() {
+        let err = load_corrupt_npz(&["block_ids"], |npz| {
+            npz.add_array("block_ids", &Array::from(vec![0u64, 0, 1, 5])).unwrap();
+        });
+        assert!(
+            matches!(err, MockDataError::BlockIds(blocks::BlockIdsError::OutOfRange { block_id: 5, n_blocks: 2 })),
+            "{:?}",
+            err
+        );
+        let err = load_corrupt_npz(&["block_ids"], |npz| {
+            npz.add_array("block_ids", &Array::from(vec![0i32, 0, -1, 1])).unwrap();
+        });
+        assert!(matches!(err, MockDataError::Overflow { name: "block_ids", value: -1 }), "{:?}", err);
+        let err = load_corrupt_npz(&["n_blocks"], |npz| {
+            npz.add_array("n_blocks", &Array::from(vec![u64::MAX])).unwrap();
+        });
+        assert!(matches!(err, MockDataError::Invalid(_)), "{:?}", err);
+    }
+
+    #[test]
+    fn load_rejects_non_finite_values() {
+        let err = load_corrupt_npz(&["resid"], |npz| {
+            npz.add_array("resid", &Array::from_shape_vec((4, 2), vec![1., -1., f64::NAN, 1., 0.5, 0., -0.5, 0.]).unwrap()).unwrap();
+        });
+        assert!(matches!(err, MockDataError::NotFinite { name: "resid" }), "{:?}", err);
+    }
+
+    #[test]
+    fn load_converts_old_layout() {
+        // Ids from one up to n_blocks, plus zero for the trailing partial
+        // block.
+        let mock_data = load_raw_npz(&["block_ids"], |npz| {
+            npz.add_array("block_ids", &Array::from(vec![1u64, 1, 2, 0])).unwrap();
+        })
+        .unwrap();
+        assert_eq!(mock_data.block_ids.n_blocks().get(), 3);
+        assert_eq!(mock_data.block_ids.view().to_vec(), vec![0, 0, 1, 2]);
+        // A complete block of the old layout must not be empty.
+        let err = load_corrupt_npz(&["block_ids"], |npz| {
+            npz.add_array("block_ids", &Array::from(vec![2u64, 2, 2, 0])).unwrap();
+        });
+        assert!(matches!(err, MockDataError::BlockIds(blocks::BlockIdsError::EmptyBlock { block_id: 1 })), "{:?}", err);
+    }
+
+    // Load mock data from a directory of npy files holding the same arrays as
+    // `load_raw_npz()`, after `write` has replaced some of them. The
+    // directory is removed afterwards.
+    fn load_raw_npy_dir<F: FnOnce(&mut NpyDir)>(test: &str, write: F) -> Result<MockData<f64>, Error> {
+        let dir = std::env::temp_dir().join(format!("swe-mockup-{}-{}", test, std::process::id()));
+        let mut npy_dir = NpyDir::create(&dir).unwrap();
+        npy_dir.add_array("n_blocks", &Array::from(vec![2u64])).unwrap();
+        npy_dir.add_array("block_ids", &Array::from(vec![0u64, 0, 1, 1])).unwrap();
+        npy_dir.add_array("x_pinv", &Array::from_elem((1, 4), 0.25)).unwrap();
+        npy_dir.add_array("resid", &Array::from_shape_vec((4, 2), vec![1., -1., -1., 1., 0.5, 0., -0.5, 0.]).unwrap()).unwrap();
+        write(&mut npy_dir);
+        let result = MockData::from_npy_dir(&dir);
+        std::fs::remove_dir_all(&dir).unwrap();
+        result
+    }
+
+    #[test]
+    fn npy_dir_converts_single_precision_and_narrow_ids() {
+        let mock_data = load_raw_npy_dir("narrow", |npy_dir| {
+            npy_dir.add_array("x_pinv", &Array::from_elem((1, 4), 0.25f32)).unwrap();
+            npy_dir.add_array("resid", &Array::from_shape_vec((4, 2), vec![1f32, -1., -1., 1., 0.5, 0., -0.5, 0.]).unwrap()).unwrap();
+            npy_dir.add_array("n_blocks", &Array::from(vec![2i32])).unwrap();
+            npy_dir.add_array("block_ids", &Array::from(vec![0i32, 0, 1, 1])).unwrap();
+        })
+        .unwrap();
+        assert_eq!(mock_data.x_pinv, Array::from_elem((1, 4), 0.25));
+        assert_eq!(mock_data.resid, Array::from_shape_vec((4, 2), vec![1., -1., -1., 1., 0.5, 0., -0.5, 0.]).unwrap());
+        assert_eq!(mock_data.block_ids.view().to_vec(), vec![0, 0, 1, 1]);
+    }
+
+    #[test]
+    fn npy_dir_rejects_negative_ids() {
+        let err = load_raw_npy_dir("negative", |npy_dir| {
+            npy_dir.add_array("block_ids", &Array::from(vec![0i32, -3, 1, 1])).unwrap();
+        })
+        .err()
+        .expect("negative id was loaded");
+        assert!(matches!(err, Error::MockData(MockDataError::Overflow { name: "block_ids", value: -3 })), "{:?}", err);
+    }
+
+    #[test]
+    fn npy_dir_reads_fortran_order() {
+        use ndarray::ShapeBuilder;
+        let resid = Array::from_shape_vec((4, 2).f(), vec![1., -1., 0.5, -0.5, -1., 1., 0., 0.]).unwrap();
+        let mock_data = load_raw_npy_dir("fortran", |npy_dir| {
+            npy_dir.add_array("resid", &resid.mapv(|x| x as f32)).unwrap();
+        })
+        .unwrap();
+        assert_eq!(mock_data.resid, resid);
+        assert!(mock_data.resid.t().is_standard_layout());
+    }
+
+    #[test]
+    fn error_distributions_need_finite_parameters() {
+        assert!(ErrorDistribution::StudentT { df: 3. }.is_valid());
+        for df in [2., f64::INFINITY, f64::NAN] {
+            assert!(!ErrorDistribution::StudentT { df }.is_valid(), "df = {}", df);
+        }
+        assert!(ErrorDistribution::SkewNormal { shape: 1e200 }.is_valid());
+        for shape in [f64::INFINITY, f64::NEG_INFINITY, f64::NAN] {
+            assert!(!ErrorDistribution::SkewNormal { shape }.is_valid(), "shape = {}", shape);
+        }
+        for (fraction, scale) in [(f64::NAN, 2.), (0.1, f64::INFINITY), (0.1, 1e200), (0.1, 0.), (1.5, 2.)] {
+            assert!(!ErrorDistribution::ContaminatedNormal { fraction, scale }.is_valid(), "fraction = {}, scale = {}", fraction, scale);
+        }
+    }
+
+    #[test]
+    fn valid_error_distributions_give_finite_residuals() {
+        for errors in [
+            ErrorDistribution::StudentT { df: 2.5 },
+            ErrorDistribution::SkewNormal { shape: 1e200 },
+            ErrorDistribution::ContaminatedNormal { fraction: 0.1, scale: 1e100 },
+        ] {
+            let params = MockParams {
+                n_obs: NonZeroUsize::new(30).unwrap(),
+                features: Features::Independent { n_feat: NonZeroUsize::new(10).unwrap() },
+                errors,
+                seed: Some(7),
+                ..MockParams::default()
+            };
+            let mock_data = MockData::<f64>::from_params(params);
+            assert!(mock_data.resid.iter().all(|x| x.is_finite()), "{}", errors);
+        }
+    }
+}
@@ -0,0 +1,626 @@
+//! MATLAB Level 5 MAT-files.
+//!
+//! MATLAB's `load` reads Level 5 MAT-files natively, so collaborators need no
+//! extra tooling to use mock data or SwE results. [`MatWriter`] writes real,
+//! dense numeric arrays of any number of dimensions, e.g. the 3-D covariance
+//! matrices of the regression coefficients, and [`MatReader`] reads them
+//! back, including files saved by MATLAB with its default compression.
+//! MATLAB stores arrays in column-major order, so arrays read from a MAT-file
+//! are in Fortran order.
+//!
+//! MATLAB cannot load variables of 2 GB or more from a Level 5 MAT-file, so
+//! larger arrays are rejected. Use a directory of npy files instead, see
+//! [`MockData::save_npy_dir()`](crate::MockData::save_npy_dir).
+//!
+//! Example:
+//! ```no_run
+//! use ndarray::Array3;
+//! use std::fs::File;
+//! use swe_mockup::mat::{MatReader, MatWriter};
+//! let cov_b = Array3::<f64>::zeros((8, 8, 1000));
+//! let mut mat = MatWriter::new(File::create("cov-b.mat")?)?;
+//! mat.add_array("covB", &cov_b)?;
+//! mat.finish()?;
+//! let mat = MatReader::new(File::open("cov-b.mat")?)?;
+//! let cov_b_read: Array3<f64> = mat.by_name("covB")?;
+//! assert_eq!(cov_b, cov_b_read);
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+
+use crate::Error;
+use ndarray::{Array, ArrayBase, Data, Dimension, IxDyn, ShapeBuilder};
+use std::io::{BufWriter, Read, Write};
+use std::ops::Range;
+
+// Data types of the data elements in a MAT-file.
+const MI_INT8: u32 = 1;
+const MI_UINT8: u32 = 2;
+const MI_INT16: u32 = 3;
+const MI_UINT16: u32 = 4;
+const MI_INT32: u32 = 5;
+const MI_UINT32: u32 = 6;
+const MI_SINGLE: u32 = 7;
+const MI_DOUBLE: u32 = 9;
+const MI_INT64: u32 = 12;
+const MI_UINT64: u32 = 13;
+const MI_MATRIX: u32 = 14;
+const MI_COMPRESSED: u32 = 15;
+
+// Classes of numeric MATLAB arrays range from double to uint64.
+const MX_DOUBLE_CLASS: u8 = 6;
+const MX_SINGLE_CLASS: u8 = 7;
+const MX_INT8_CLASS: u8 = 8;
+const MX_UINT8_CLASS: u8 = 9;
+const MX_INT16_CLASS: u8 = 10;
+const MX_UINT16_CLASS: u8 = 11;
+const MX_INT32_CLASS: u8 = 12;
+const MX_UINT32_CLASS: u8 = 13;
+const MX_INT64_CLASS: u8 = 14;
+const MX_UINT64_CLASS: u8 = 15;
+
+// Array flag for complex arrays.
+const FLAG_COMPLEX: u32 = 0x0800;
+
+/// Largest variable, in bytes, that MATLAB can load from a Level 5 MAT-file.
+pub const MAX_BYTES: usize = i32::MAX as usize;
+
+/// Error reading or writing a MAT-file.
+#[derive(Debug)]
+pub enum MatError {
+    /// The file is not a little-endian Level 5 MAT-file.
+    Header,
+    /// The file is truncated or otherwise malformed.
+    Malformed(String),
+    /// There is no variable with this name.
+    Missing {
+        /// Name of the variable
+        name: String,
+    },
+    /// The variable is not a real, dense numeric array, e.g. it is a cell
+    /// array, a struct, a sparse or a complex array.
+    Unsupported {
+        /// Name of the variable
+        name: String,
+    },
+    /// The variable cannot be given the requested number of dimensions.
+    Shape {
+        /// Name of the variable
+        name: String,
+        /// Requested number of dimensions
+        expected: usize,
+        /// Dimensions of the variable in the file
+        found: Vec<usize>,
+    },
+    /// Some values of the variable cannot be represented in the requested
+    /// type, e.g. fractional or negative values for an unsigned integer type.
+    Type {
+        /// Name of the variable
+        name: String,
+        /// MATLAB class of the requested type
+        expected: &'static str,
+    },
+    /// The variable is too large for MATLAB to load from a Level 5 MAT-file.
+    TooLarge {
+        /// Name of the variable
+        name: String,
+    },
+    /// The name is not a valid MATLAB variable name.
+    Name {
+        /// The offending name
+        name: String,
+    },
+}
+impl std::fmt::Display for MatError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            MatError::Header => write!(f, "Not a little-endian Level 5 MAT-file."),
+            MatError::Malformed(msg) => write!(f, "Malformed MAT-file: {}", msg),
+            MatError::Missing { name } => write!(f, "MAT-file has no variable {}.", name),
+            MatError::Unsupported { name } => {
+                write!(f, "Variable {} is not a real, dense numeric array.", name)
+            }
+            MatError::Shape { name, expected, found } => write!(
+                f,
+                "Variable {} has dimensions {:?} but expected {} dimensions.",
+                name, found, expected
+            ),
+            MatError::Type { name, expected } => {
+                write!(f, "Variable {} has values which are not valid {}.", name, expected)
+            }
+            MatError::TooLarge { name } => {
+                write!(f, "Variable {} is too large for a Level 5 MAT-file.", name)
+            }
+            MatError::Name { name } => write!(f, "{} is not a valid MATLAB variable name.", name),
+        }
+    }
+}
+impl std::error::Error for MatError {}
+
+/// Element types of real, dense numeric MATLAB arrays.
+pub trait MatElement: Copy {
+    /// MATLAB class of arrays with this element type, e.g. `double`
+    const CLASS_NAME: &'static str;
+    /// Code of the MATLAB class in a MAT-file
+    const CLASS: u8;
+    /// Data type of the elements in a MAT-file
+    const MI_TYPE: u32;
+
+    /// Write the element in little-endian byte order.
+    fn write_le<W: Write>(self, w: &mut W) -> std::io::Result<()>;
+
+    /// Convert a floating point value read from a file, or None if it cannot
+    /// be represented, e.g. a fractional value for an integer type.
+    fn from_f64(x: f64) -> Option<Self>;
+
+    /// Convert an integer value read from a file, or None if it cannot be
+    /// represented, e.g. a negative value for an unsigned type.
+    fn from_i128(x: i128) -> Option<Self>;
+}
+macro_rules! impl_mat_float {
+    ($t:ty, $class_name:expr, $class:expr, $mi_type:expr) => {
+        impl MatElement for $t {
+            const CLASS_NAME: &'static str = $class_name;
+            const CLASS: u8 = $class;
+            const MI_TYPE: u32 = $mi_type;
+            fn write_le<W: Write>(self, w: &mut W) -> std::io::Result<()> {
+                w.write_all(&self.to_le_bytes())
+            }
+            // Floating point types accept any value, rounding if need be.
+            fn from_f64(x: f64) -> Option<Self> {
+                Some(x as $t)
+            }
+            fn from_i128(x: i128) -> Option<Self> {
+                Some(x as $t)
+            }
+        }
+    };
+}
+macro_rules! impl_mat_int {
+    ($t:ty, $class_name:expr, $class:expr, $mi_type:expr) => {
+        impl MatElement for $t {
+            const CLASS_NAME: &'static str = $class_name;
+            const CLASS: u8 = $class;
+            const MI_TYPE: u32 = $mi_type;
+            fn write_le<W: Write>(self, w: &mut W) -> std::io::Result<()> {
+                w.write_all(&self.to_le_bytes())
+            }
+            // MATLAB stores ids as doubles by default, which are accepted if
+            // they are whole numbers in range.
+            fn from_f64(x: f64) -> Option<Self> {
+                if x.fract() == 0. && x.abs() < 1e38 {
+                    <$t>::try_from(x as i128).ok()
+                } else {
+                    None
+                }
+            }
+            fn from_i128(x: i128) -> Option<Self> {
+                <$t>::try_from(x).ok()
+            }
+        }
+    };
+}
+impl_mat_float!(f64, "double", MX_DOUBLE_CLASS, MI_DOUBLE);
+impl_mat_float!(f32, "single", MX_SINGLE_CLASS, MI_SINGLE);
+impl_mat_int!(i8, "int8", MX_INT8_CLASS, MI_INT8);
+impl_mat_int!(u8, "uint8", MX_UINT8_CLASS, MI_UINT8);
+impl_mat_int!(i16, "int16", MX_INT16_CLASS, MI_INT16);
+impl_mat_int!(u16, "uint16", MX_UINT16_CLASS, MI_UINT16);
+impl_mat_int!(i32, "int32", MX_INT32_CLASS, MI_INT32);
+impl_mat_int!(u32, "uint32", MX_UINT32_CLASS, MI_UINT32);
+impl_mat_int!(i64, "int64", MX_INT64_CLASS, MI_INT64);
+impl_mat_int!(u64, "uint64", MX_UINT64_CLASS, MI_UINT64);
+
+/// Writer for Level 5 MAT-files.
+pub struct MatWriter<W: Write> {
+    w: BufWriter<W>,
+}
+impl<W: Write> MatWriter<W> {
+    /// Start a new MAT-file by writing its header.
+    pub fn new(w: W) -> Result<Self, Error> {
+        let mut w = BufWriter::new(w);
+        // Descriptive text padded with spaces, no subsystem data, version
+        // 0x0100 and the endian indicator.
+        let mut header = [b' '; 128];
+        let text = b"MATLAB 5.0 MAT-file, written by swe-mockup";
+        header[..text.len()].copy_from_slice(text);
+        header[116..124].fill(0);
+        header[124..126].copy_from_slice(&0x0100u16.to_le_bytes());
+        header[126..128].copy_from_slice(b"IM");
+        w.write_all(&header)?;
+        Ok(Self { w })
+    }
+
+    /// Add a real, dense numeric array as a variable. Arrays with fewer than
+    /// two dimensions are saved as column vectors, or as a scalar. Returns an
+    /// error if the name is not a valid MATLAB variable name or the array is
+    /// too large for a Level 5 MAT-file.
+    pub fn add_array<S, D>(&mut self, name: &str, array: &ArrayBase<S, D>) -> Result<(), Error>
+    where
+        S: Data,
+        S::Elem: MatElement,
+        D: Dimension,
+    {
+        // MATLAB variable names start with a letter, followed by letters,
+        // digits or underscores, and have at most 63 characters.
+        let valid_name = name.len() <= 63
+            && name.starts_with(|c: char| c.is_ascii_alphabetic())
+            && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+        if !valid_name {
+            return Err(MatError::Name { name: name.to_string() }.into());
+        }
+
+        // MATLAB arrays have at least two dimensions.
+        let mut dims = array.shape().to_vec();
+        while dims.len() < 2 {
+            dims.push(1);
+        }
+        let n_bytes = check_size::<S::Elem>(name, array.len())?;
+
+        // The matrix element holds the array flags, dimensions, name and
+        // data, each in a sub-element padded to a multiple of 8 bytes.
+        let n_bytes_matrix = 16 + 8 + padded(4 * dims.len()) + 8 + padded(name.len()) + 8 + padded(n_bytes);
+        let w = &mut self.w;
+        write_tag(w, MI_MATRIX, n_bytes_matrix)?;
+        write_tag(w, MI_UINT32, 8)?;
+        w.write_all(&u32::from(S::Elem::CLASS).to_le_bytes())?;
+        w.write_all(&0u32.to_le_bytes())?;
+        write_tag(w, MI_INT32, 4 * dims.len())?;
+        for &dim in dims.iter() {
+            w.write_all(&(dim as i32).to_le_bytes())?;
+        }
+        write_padding(w, 4 * dims.len())?;
+        write_tag(w, MI_INT8, name.len())?;
+        w.write_all(name.as_bytes())?;
+        write_padding(w, name.len())?;
+        write_tag(w, S::Elem::MI_TYPE, n_bytes)?;
+        // Reversing the axes iterates in column-major order.
+        for &x in array.view().reversed_axes().iter() {
+            x.write_le(w)?;
+        }
+        write_padding(w, n_bytes)?;
+        Ok(())
+    }
+
+    /// Flush the file and return the underlying writer.
+    pub fn finish(self) -> Result<W, Error> {
+        Ok(self.w.into_inner().map_err(|err| err.into_error())?)
+    }
+}
+
+// Number of bytes of the data of a variable with `len` elements of type T, or
+// an error if the variable is too large for a Level 5 MAT-file.
+pub(crate) fn check_size<T>(name: &str, len: usize) -> Result<usize, MatError> {
+    match len.checked_mul(std::mem::size_of::<T>()) {
+        Some(n_bytes) if n_bytes <= MAX_BYTES => Ok(n_bytes),
+        _ => Err(MatError::TooLarge { name: name.to_string() }),
+    }
+}
+
+// Number of bytes rounded up to a multiple of 8.
+fn padded(n_bytes: usize) -> usize {
+    n_bytes.next_multiple_of(8)
+}
+
+// Write the tag of a data element in the regular format. The number of bytes
+// has already been checked to fit.
+fn write_tag<W: Write>(w: &mut W, mi_type: u32, n_bytes: usize) -> std::io::Result<()> {
+    w.write_all(&mi_type.to_le_bytes())?;
+    w.write_all(&(n_bytes as u32).to_le_bytes())
+}
+
+// Pad data of n_bytes to a multiple of 8 bytes.
+fn write_padding<W: Write>(w: &mut W, n_bytes: usize) -> std::io::Result<()> {
+    w.write_all(&[0; 8][..padded(n_bytes) - n_bytes])
+}
+
+// A variable read from a MAT-file.
+struct Variable {
+    name: String,
+    // Whether the variable is a real, dense numeric array
+    numeric: bool,
+    // Dimensions in MATLAB order
+    dims: Vec<usize>,
+    // Data type of the real part of the data
+    mi_type: u32,
+    // Range of the bytes of the real part of the data, in the inflated
+    // element if the variable is compressed, otherwise in the file
+    data: Range<usize>,
+    // Inflated element of a compressed variable
+    inflated: Option<Vec<u8>>,
+}
+impl Variable {
+    // Parse the data of a matrix element, which is part of `buffer`. The
+    // data of the variable is not copied, only its range in `buffer` kept.
+    fn parse(buffer: &[u8], bytes: &[u8]) -> Result<Self, MatError> {
+        // Array flags hold the class and whether the array is complex.
+        let (mi_type, flags, rest) = element(bytes)?;
+        if mi_type != MI_UINT32 || flags.len() != 8 {
+            return Err(MatError::Malformed("invalid array flags".to_string()));
+        }
+        let flags = u32::from_le_bytes(flags[..4].try_into().unwrap());
+        let class = (flags & 0xff) as u8;
+        let numeric = (MX_DOUBLE_CLASS..=MX_UINT64_CLASS).contains(&class) && flags & FLAG_COMPLEX == 0;
+
+        // Dimensions are 32-bit signed integers.
+        let (mi_type, dims, rest) = element(rest)?;
+        if mi_type != MI_INT32 || dims.len() % 4 != 0 {
+            return Err(MatError::Malformed("invalid dimensions".to_string()));
+        }
+        let dims = dims
+            .chunks_exact(4)
+            .map(|dim| usize::try_from(i32::from_le_bytes(dim.try_into().unwrap())))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|_| MatError::Malformed("negative dimension".to_string()))?;
+
+        // Name is ASCII.
+        let (_, name, rest) = element(rest)?;
+        let name = String::from_utf8(name.to_vec()).map_err(|_| MatError::Malformed("invalid name".to_string()))?;
+
+        // Only numeric arrays have a real part we know how to read.
+        if !numeric {
+            return Ok(Self { name, numeric, dims, mi_type: 0, data: 0..0, inflated: None });
+        }
+        let (mi_type, data, _) = element(rest)?;
+        let n_values = dims.iter().product::<usize>();
+        if mi_size(mi_type).map(|size| n_values.checked_mul(size)) != Some(Some(data.len())) {
+            return Err(MatError::Malformed(format!("variable {} has the wrong amount of data", name)));
+        }
+        // Both slices are of the same buffer.
+        let start = data.as_ptr() as usize - buffer.as_ptr() as usize;
+        Ok(Self { name, numeric, dims, mi_type, data: start..start + data.len(), inflated: None })
+    }
+}
+
+// Size in bytes of a numeric data type, or None if it is not numeric.
+fn mi_size(mi_type: u32) -> Option<usize> {
+    match mi_type {
+        MI_INT8 | MI_UINT8 => Some(1),
+        MI_INT16 | MI_UINT16 => Some(2),
+        MI_INT32 | MI_UINT32 | MI_SINGLE => Some(4),
+        MI_DOUBLE | MI_INT64 | MI_UINT64 => Some(8),
+        _ => None,
+    }
+}
+
+// Split the next data element off the front of the bytes. Returns its data
+// type, its data and the remaining bytes.
+fn element(bytes: &[u8]) -> Result<(u32, &[u8], &[u8]), MatError> {
+    let truncated = || MatError::Malformed("truncated data element".to_string());
+    let tag = u32::from_le_bytes(bytes.get(..4).ok_or_else(truncated)?.try_into().unwrap());
+    if tag >> 16 != 0 {
+        // Small data element with up to 4 bytes of data packed into the tag.
+        let (mi_type, n_bytes) = (tag & 0xffff, (tag >> 16) as usize);
+        if n_bytes > 4 || bytes.len() < 8 {
+            return Err(truncated());
+        }
+        return Ok((mi_type, &bytes[4..4 + n_bytes], &bytes[8..]));
+    }
+    let n_bytes = u32::from_le_bytes(bytes.get(4..8).ok_or_else(truncated)?.try_into().unwrap()) as usize;
+    let data = bytes.get(8..8 + n_bytes).ok_or_else(truncated)?;
+    // Elements are padded to a multiple of 8 bytes, except compressed ones.
+    let next = if tag == MI_COMPRESSED { 8 + n_bytes } else { padded(8 + n_bytes) };
+    Ok((tag, data, bytes.get(next..).unwrap_or(&[])))
+}
+
+// Decode data, converting each value to T. Returns None if any value cannot be
+// represented in T.
+fn decode<T: MatElement>(mi_type: u32, bytes: &[u8]) -> Option<Vec<T>> {
+    macro_rules! convert {
+        ($t:ty, $from:ident, $wide:ty) => {
+            bytes
+                .chunks_exact(std::mem::size_of::<$t>())
+                .map(|x| T::$from(<$t>::from_le_bytes(x.try_into().unwrap()) as $wide))
+                .collect()
+        };
+    }
+    match mi_type {
+        MI_INT8 => convert!(i8, from_i128, i128),
+        MI_UINT8 => convert!(u8, from_i128, i128),
+        MI_INT16 => convert!(i16, from_i128, i128),
+        MI_UINT16 => convert!(u16, from_i128, i128),
+        MI_INT32 => convert!(i32, from_i128, i128),
+        MI_UINT32 => convert!(u32, from_i128, i128),
+        MI_INT64 => convert!(i64, from_i128, i128),
+        MI_UINT64 => convert!(u64, from_i128, i128),
+        MI_SINGLE => convert!(f32, from_f64, f64),
+        MI_DOUBLE => convert!(f64, from_f64, f64),
+        // Checked when the variable was parsed.
+        _ => unreachable!(),
+    }
+}
+
+// Dimensions of a variable with the requested number of dimensions. MATLAB
+// arrays have at least two dimensions and drop trailing singleton
+// dimensions, so remove or append trailing singleton dimensions as needed. A
+// row vector is also accepted as a 1-D array.
+fn fit_dims(dims: &[usize], ndim: usize) -> Option<Vec<usize>> {
+    if ndim == 1 && dims.len() == 2 && dims[0] == 1 {
+        return Some(vec![dims[1]]);
+    }
+    let mut dims = dims.to_vec();
+    while dims.len() > ndim && dims.last() == Some(&1) {
+        dims.pop();
+    }
+    while dims.len() < ndim {
+        dims.push(1);
+    }
+    (dims.len() == ndim).then_some(dims)
+}
+
+/// Reader for Level 5 MAT-files.
+pub struct MatReader {
+    // Bytes of the whole file
+    bytes: Vec<u8>,
+    variables: Vec<Variable>,
+}
+impl MatReader {
+    /// Read all the variables in a MAT-file, including compressed ones.
+    /// Variables other than real, dense numeric arrays are skipped when read
+    /// and cannot be converted to arrays.
+    pub fn new<R: Read>(mut r: R) -> Result<Self, Error> {
+        let mut bytes = Vec::new();
+        r.read_to_end(&mut bytes)?;
+        if bytes.len() < 128 || bytes[124..126] != 0x0100u16.to_le_bytes() || &bytes[126..128] != b"IM" {
+            return Err(MatError::Header.into());
+        }
+        let mut variables = Vec::new();
+        let mut rest = &bytes[128..];
+        while !rest.is_empty() {
+            let (mi_type, data, next) = element(rest)?;
+            match mi_type {
+                MI_MATRIX => variables.push(Variable::parse(&bytes, data)?),
+                MI_COMPRESSED => {
+                    // A compressed element holds one zlib-compressed element,
+                    // which the variable keeps to decode from.
+                    let mut inflated = Vec::new();
+                    flate2::read::ZlibDecoder::new(data)
+                        .read_to_end(&mut inflated)
+                        .map_err(|err| MatError::Malformed(format!("invalid compressed data: {}", err)))?;
+                    let (mi_type, data, _) = element(&inflated)?;
+                    if mi_type == MI_MATRIX {
+                        let mut variable = Variable::parse(&inflated, data)?;
+                        variable.inflated = Some(inflated);
+                        variables.push(variable);
+                    }
+                }
+                // Skip anything else.
+                _ => {}
+            }
+            rest = next;
+        }
+        Ok(Self { bytes, variables })
+    }
+
+    /// Names of the variables in the file.
+    pub fn names(&self) -> Vec<&str> {
+        self.variables.iter().map(|variable| variable.name.as_str()).collect()
+    }
+
+    /// Read a variable as an array of type T in Fortran order. Values of any
+    /// numeric class are converted to T if they can be represented, e.g.
+    /// whole numbers stored as doubles can be read as integers. Trailing
+    /// singleton dimensions are added or removed to get the requested
+    /// number of dimensions.
+    pub fn by_name<T, D>(&self, name: &str) -> Result<Array<T, D>, Error>
+    where
+        T: MatElement,
+        D: Dimension,
+    {
+        self.try_by_name(name)?.ok_or_else(|| {
+            MatError::Type {
+                name: name.to_string(),
+                expected: T::CLASS_NAME,
+            }
+            .into()
+        })
+    }
+
+    // Read a variable as an array of type T, or None if its values cannot be
+    // represented in T.
+    pub(crate) fn try_by_name<T, D>(&self, name: &str) -> Result<Option<Array<T, D>>, MatError>
+    where
+        T: MatElement,
+        D: Dimension,
+    {
+        let variable = self
+            .variables
+            .iter()
+            .find(|variable| variable.name == name)
+            .ok_or_else(|| MatError::Missing { name: name.to_string() })?;
+        if !variable.numeric {
+            return Err(MatError::Unsupported { name: name.to_string() });
+        }
+        let dims = match D::NDIM {
+            Some(ndim) => fit_dims(&variable.dims, ndim).ok_or_else(|| MatError::Shape {
+                name: name.to_string(),
+                expected: ndim,
+                found: variable.dims.clone(),
+            })?,
+            None => variable.dims.clone(),
+        };
+        let bytes = variable.inflated.as_deref().unwrap_or(&self.bytes);
+        let values = match decode(variable.mi_type, &bytes[variable.data.clone()]) {
+            Some(values) => values,
+            None => return Ok(None),
+        };
+        // The number of values was checked when the variable was parsed.
+        let array = Array::from_shape_vec(IxDyn(&dims).f(), values).unwrap();
+        Ok(Some(array.into_dimensionality().unwrap()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::write::ZlibEncoder;
+    use ndarray::{Array1, Array2, Array3, Ix1, Ix3};
+    use std::io::Cursor;
+
+    // Write a few arrays of different types and numbers of dimensions.
+    fn write_arrays() -> (Array3<f64>, Array2<f32>, Array1<u64>, Vec<u8>) {
+        let cov = Array3::from_shape_fn((3, 4, 5), |(i, j, k)| (i * 100 + j * 10 + k) as f64);
+        let x = Array2::from_shape_fn((2, 3), |(i, j)| i as f32 - 0.5 * j as f32);
+        let ids = Array1::from(vec![0u64, 1, 1, 2]);
+        let mut mat = MatWriter::new(Vec::new()).unwrap();
+        mat.add_array("covB", &cov).unwrap();
+        mat.add_array("x", &x).unwrap();
+        mat.add_array("ids", &ids).unwrap();
+        (cov, x, ids, mat.finish().unwrap())
+    }
+
+    // Wrap every data element after the header in its own miCOMPRESSED
+    // element, as MATLAB does when saving with -v7.
+    fn compress(bytes: &[u8]) -> Vec<u8> {
+        let mut out = bytes[..128].to_vec();
+        let mut rest = &bytes[128..];
+        while !rest.is_empty() {
+            let n_bytes = u32::from_le_bytes(rest[4..8].try_into().unwrap()) as usize;
+            let mut encoder = ZlibEncoder::new(Vec::new(), flate2::Compression::default());
+            encoder.write_all(&rest[..8 + n_bytes]).unwrap();
+            let compressed = encoder.finish().unwrap();
+            out.extend_from_slice(&MI_COMPRESSED.to_le_bytes());
+            out.extend_from_slice(&(compressed.len() as u32).to_le_bytes());
+            out.extend_from_slice(&compressed);
+            rest = &rest[8 + n_bytes..];
+        }
+        out
+    }
+
+    #[test]
+    fn round_trip() {
+        let (cov, x, ids, bytes) = write_arrays();
+        let mat = MatReader::new(Cursor::new(bytes)).unwrap();
+        assert_eq!(mat.names(), vec!["covB", "x", "ids"]);
+        assert_eq!(mat.by_name::<f64, Ix3>("covB").unwrap(), cov);
+        assert_eq!(mat.by_name::<f32, _>("x").unwrap(), x);
+        assert_eq!(mat.by_name::<u64, Ix1>("ids").unwrap(), ids);
+        assert!(mat.by_name::<f64, Ix1>("missing").is_err());
+    }
+
+    #[test]
+    fn round_trip_compressed() {
+        let (cov, x, ids, bytes) = write_arrays();
+        let mat = MatReader::new(Cursor::new(compress(&bytes))).unwrap();
+        assert_eq!(mat.names(), vec!["covB", "x", "ids"]);
+        assert_eq!(mat.by_name::<f64, Ix3>("covB").unwrap(), cov);
+        assert_eq!(mat.by_name::<f32, _>("x").unwrap(), x);
+        assert_eq!(mat.by_name::<u64, Ix1>("ids").unwrap(), ids);
+    }
+
+    #[test]
+    fn too_large_array_is_rejected_before_writing() {
+        // A broadcast view is as large as a real array without the memory.
+        let zero = Array1::<f64>::zeros(1);
+        let huge = zero.broadcast(MAX_BYTES / 8 + 1).unwrap();
+        let small = Array1::from(vec![1., 2.]);
+        let mut mat = MatWriter::new(Vec::new()).unwrap();
+        mat.add_array("small", &small).unwrap();
+        let err = mat.add_array("resid", &huge).unwrap_err();
+        assert!(matches!(err, Error::Mat(MatError::TooLarge { ref name }) if name == "resid"));
+        // Nothing of the rejected array was written, so the file is intact.
+        let bytes = mat.finish().unwrap();
+        let mat = MatReader::new(Cursor::new(bytes)).unwrap();
+        assert_eq!(mat.names(), vec!["small"]);
+        assert_eq!(mat.by_name::<f64, Ix1>("small").unwrap(), small);
+    }
+}
@@ -0,0 +1,623 @@
+//! Reading and writing npz files and directories of npy files.
+//!
+//! Mock data can also be saved to MATLAB's own MAT-files, see [`crate::mat`].
+//!
+//! An npz file is a zip archive of npy files. Mock data sets with tens of
+//! thousands of features run to several gigabytes when stored without
+//! compression, so [`MockData::save_npz_file_with()`](crate::MockData::save_npz_file_with)
+//! can deflate each array, as `numpy.savez_compressed()` does. Higher levels
+//! give smaller files but take longer to write; reading is about equally fast
+//! at any level. Compressed files are read transparently by
+//! [`MockData::from_npz_file()`](crate::MockData::from_npz_file).
+//!
+//! With the `hdf5` feature, mock data can be saved to HDF5 files too, see
+//! the `h5` module.
+//!
+//! MATLAB's `readNPY()` cannot read npz files, so the same arrays can also be
+//! saved to a directory with one npy file per array, named after the array,
+//! e.g. `mock-data/x_pinv.npy`, see
+//! [`MockData::save_npy_dir()`](crate::MockData::save_npy_dir) and
+//! [`MockData::from_npy_dir()`](crate::MockData::from_npy_dir).
+//!
+//! Example:
+//! ```no_run
+//! use std::fs::File;
+//! # use swe_mockup::{MockData, MockParams};
+//! use swe_mockup::npz::Compression;
+//! let mock_data = MockData::<f64>::from_params(MockParams::default());
+//! mock_data.save_npz_file_with(File::create("mock-data.npz")?, Compression::Deflate { level: 6 })?;
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+
+use crate::mat::{self, MatElement, MatReader, MatWriter};
+use crate::Error;
+use ndarray::{Array, ArrayBase, Data, Dimension};
+use ndarray_npy::{
+    NpzReader, ReadNpyError, ReadNpyExt, ReadNpzError, ReadableElement, WritableElement, WriteNpyExt, WriteNpzError,
+};
+use flate2::write::DeflateEncoder;
+use flate2::Crc;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, ErrorKind, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+/// Compression of the arrays in an npz file.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Compression {
+    /// Arrays are stored without compression, as by `numpy.savez()`.
+    #[default]
+    Stored,
+    /// Arrays are compressed with deflate, as by `numpy.savez_compressed()`.
+    Deflate {
+        /// Compression level from 0 (fastest) to 9 (smallest)
+        level: u32,
+    },
+}
+impl Compression {
+    /// Check if the compression level is valid.
+    pub fn is_valid(&self) -> bool {
+        match self {
+            Compression::Stored => true,
+            Compression::Deflate { level } => *level <= 9,
+        }
+    }
+}
+impl std::fmt::Display for Compression {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Compression::Stored => write!(f, "no compression"),
+            Compression::Deflate { level } => write!(f, "deflate compression at level {}", level),
+        }
+    }
+}
+
+/// Element types of arrays that can be stored in HDF5 files.
+///
+/// With the `hdf5` feature these are the types implementing `hdf5::H5Type`,
+/// which include all the primitive numeric types. Without it every type
+/// qualifies, so that the bound does not restrict [`MockData`](crate::MockData).
+#[cfg(feature = "hdf5")]
+pub trait H5Element: hdf5::H5Type {}
+#[cfg(feature = "hdf5")]
+impl<T: hdf5::H5Type> H5Element for T {}
+/// Element types of arrays that can be stored in HDF5 files.
+///
+/// With the `hdf5` feature these are the types implementing `hdf5::H5Type`,
+/// which include all the primitive numeric types. Without it every type
+/// qualifies, so that the bound does not restrict [`MockData`](crate::MockData).
+#[cfg(not(feature = "hdf5"))]
+pub trait H5Element {}
+#[cfg(not(feature = "hdf5"))]
+impl<T> H5Element for T {}
+
+// Destination of the named arrays of mock data.
+pub(crate) trait ArraySink {
+    // Add an array.
+    fn add_array<S, D>(&mut self, name: &str, array: &ArrayBase<S, D>) -> Result<(), Error>
+    where
+        S: Data,
+        S::Elem: WritableElement + MatElement + H5Element,
+        D: Dimension;
+}
+
+// Source of the named arrays of mock data.
+pub(crate) trait ArraySource {
+    // Whether there is an array with this name.
+    fn has(&self, name: &str) -> bool;
+
+    // Read an array. Returns None if the array has a different element type
+    // than T, so that the caller can try again with another type.
+    fn read<T, D>(&mut self, name: &str) -> Result<Option<Array<T, D>>, Error>
+    where
+        T: ReadableElement + MatElement + H5Element,
+        D: Dimension;
+}
+
+// Writer for npz files. Like ndarray_npy::NpzWriter, but with a choice of
+// compression level. The zip archive is written here rather than with the zip
+// crate, as the version ndarray-npy depends on has no choice of level either.
+pub(crate) struct NpzWriter<W: Write + Seek> {
+    w: W,
+    compression: Compression,
+    // Central directory record of each array written so far
+    entries: Vec<ZipEntry>,
+}
+impl<W: Write + Seek> NpzWriter<W> {
+    // Start a new npz file. Returns an error if the compression is not valid.
+    pub(crate) fn new(w: W, compression: Compression) -> Result<Self, Error> {
+        if !compression.is_valid() {
+            return Err(Error::InvalidCompression(compression));
+        }
+        Ok(Self {
+            w,
+            compression,
+            entries: Vec::new(),
+        })
+    }
+
+    // Write the central directory and flush the file.
+    pub(crate) fn finish(mut self) -> Result<(), Error> {
+        self.write_central_directory().map_err(|source| Error::Zip { array: None, source })?;
+        self.w.flush()?;
+        Ok(())
+    }
+
+    // Write the local header, the npy file and then the sizes and checksum in
+    // the local header, which are only known afterwards.
+    fn write_entry<S, D>(&mut self, name: &str, array: &ArrayBase<S, D>) -> Result<(), Error>
+    where
+        S: Data,
+        S::Elem: WritableElement,
+        D: Dimension,
+    {
+        let zip_err = |source| Error::Zip { array: Some(name.to_string()), source };
+        // Members of 4 GiB or more need zip64 extensions. Leave plenty of room
+        // for the npy header and for data that do not compress.
+        let n_bytes = array.len().saturating_mul(std::mem::size_of::<S::Elem>());
+        let mut entry = ZipEntry {
+            name: name.to_string(),
+            method: match self.compression {
+                Compression::Stored => 0,
+                Compression::Deflate { .. } => 8,
+            },
+            zip64: n_bytes >= 1 << 31,
+            crc: 0,
+            compressed: 0,
+            uncompressed: 0,
+            offset: self.w.stream_position().map_err(zip_err)?,
+        };
+        entry.write_local_header(&mut self.w).map_err(zip_err)?;
+
+        // Write the npy file, deflating it if need be.
+        let data_start = self.w.stream_position().map_err(zip_err)?;
+        let mut counter = Counter::new(match self.compression {
+            Compression::Stored => Encoder::Stored(&mut self.w),
+            Compression::Deflate { level } => {
+                Encoder::Deflate(DeflateEncoder::new(&mut self.w, flate2::Compression::new(level)))
+            }
+        });
+        array
+            .write_npy(BufWriter::new(&mut counter))
+            .map_err(WriteNpzError::Npy)
+            .map_err(Error::write_npz(name))?;
+        (entry.crc, entry.uncompressed) = counter.finish().map_err(zip_err)?;
+        let data_end = self.w.stream_position().map_err(zip_err)?;
+        entry.compressed = data_end - data_start;
+
+        // Fill in the local header and return to the end of the file.
+        self.w.seek(SeekFrom::Start(entry.offset)).map_err(zip_err)?;
+        entry.write_local_header(&mut self.w).map_err(zip_err)?;
+        self.w.seek(SeekFrom::Start(data_end)).map_err(zip_err)?;
+        self.entries.push(entry);
+        Ok(())
+    }
+
+    // Write the central directory, with zip64 records if there are too many
+    // entries or the archive is too large for the original format.
+    fn write_central_directory(&mut self) -> std::io::Result<()> {
+        let start = self.w.stream_position()?;
+        for entry in &self.entries {
+            entry.write_central_header(&mut self.w)?;
+        }
+        let end = self.w.stream_position()?;
+        let (n_entries, size) = (self.entries.len() as u64, end - start);
+        if n_entries >= 0xffff || size >= 0xffff_ffff || start >= 0xffff_ffff {
+            // Zip64 end of central directory record and its locator.
+            self.w.write_all(&0x0606_4b50u32.to_le_bytes())?;
+            self.w.write_all(&44u64.to_le_bytes())?;
+            self.w.write_all(&45u16.to_le_bytes())?;
+            self.w.write_all(&45u16.to_le_bytes())?;
+            self.w.write_all(&[0; 8])?;
+            self.w.write_all(&n_entries.to_le_bytes())?;
+            self.w.write_all(&n_entries.to_le_bytes())?;
+            self.w.write_all(&size.to_le_bytes())?;
+            self.w.write_all(&start.to_le_bytes())?;
+            self.w.write_all(&0x0706_4b50u32.to_le_bytes())?;
+            self.w.write_all(&0u32.to_le_bytes())?;
+            self.w.write_all(&end.to_le_bytes())?;
+            self.w.write_all(&1u32.to_le_bytes())?;
+        }
+        // End of central directory record, pointing to the zip64 record for
+        // any values that do not fit.
+        let n_entries = n_entries.min(0xffff) as u16;
+        self.w.write_all(&0x0605_4b50u32.to_le_bytes())?;
+        self.w.write_all(&[0; 4])?;
+        self.w.write_all(&n_entries.to_le_bytes())?;
+        self.w.write_all(&n_entries.to_le_bytes())?;
+        self.w.write_all(&clamp_u32(size).to_le_bytes())?;
+        self.w.write_all(&clamp_u32(start).to_le_bytes())?;
+        self.w.write_all(&0u16.to_le_bytes())
+    }
+}
+impl<W: Write + Seek> ArraySink for NpzWriter<W> {
+    fn add_array<S, D>(&mut self, name: &str, array: &ArrayBase<S, D>) -> Result<(), Error>
+    where
+        S: Data,
+        S::Elem: WritableElement + MatElement + H5Element,
+        D: Dimension,
+    {
+        self.write_entry(name, array)
+    }
+}
+
+// Entry of a zip archive, holding one npy file.
+struct ZipEntry {
+    name: String,
+    // Compression method, 0 for stored or 8 for deflated
+    method: u16,
+    // Whether the local header has a zip64 extra field for the sizes
+    zip64: bool,
+    // CRC-32 of the uncompressed data
+    crc: u32,
+    // Size of the data in the archive
+    compressed: u64,
+    // Size of the npy file
+    uncompressed: u64,
+    // Offset of the local header from the start of the archive
+    offset: u64,
+}
+impl ZipEntry {
+    // Modification time and date of every entry, midnight on 1 January 1980,
+    // the earliest MS-DOS date, so that the same arrays give the same file.
+    const TIME: u16 = 0;
+    const DATE: u16 = (1 << 5) | 1;
+    // Flag for names encoded in UTF-8.
+    const UTF8: u16 = 1 << 11;
+
+    // Write the local header, which precedes the data.
+    fn write_local_header<W: Write>(&self, w: &mut W) -> std::io::Result<()> {
+        let (compressed, uncompressed) = if self.zip64 {
+            (0xffff_ffff, 0xffff_ffff)
+        } else {
+            (to_u32(self.compressed)?, to_u32(self.uncompressed)?)
+        };
+        w.write_all(&0x0403_4b50u32.to_le_bytes())?;
+        w.write_all(&self.version().to_le_bytes())?;
+        w.write_all(&Self::UTF8.to_le_bytes())?;
+        w.write_all(&self.method.to_le_bytes())?;
+        w.write_all(&Self::TIME.to_le_bytes())?;
+        w.write_all(&Self::DATE.to_le_bytes())?;
+        w.write_all(&self.crc.to_le_bytes())?;
+        w.write_all(&compressed.to_le_bytes())?;
+        w.write_all(&uncompressed.to_le_bytes())?;
+        w.write_all(&(self.name.len() as u16).to_le_bytes())?;
+        w.write_all(&(if self.zip64 { 20u16 } else { 0 }).to_le_bytes())?;
+        w.write_all(self.name.as_bytes())?;
+        if self.zip64 {
+            w.write_all(&1u16.to_le_bytes())?;
+            w.write_all(&16u16.to_le_bytes())?;
+            w.write_all(&self.uncompressed.to_le_bytes())?;
+            w.write_all(&self.compressed.to_le_bytes())?;
+        }
+        Ok(())
+    }
+
+    // Write the header in the central directory, with a zip64 extra field
+    // for any values that do not fit in 32 bits.
+    fn write_central_header<W: Write>(&self, w: &mut W) -> std::io::Result<()> {
+        let mut extra = Vec::new();
+        for value in [self.uncompressed, self.compressed, self.offset] {
+            if value >= 0xffff_ffff {
+                extra.extend_from_slice(&value.to_le_bytes());
+            }
+        }
+        w.write_all(&0x0201_4b50u32.to_le_bytes())?;
+        w.write_all(&self.version().to_le_bytes())?;
+        w.write_all(&self.version().to_le_bytes())?;
+        w.write_all(&Self::UTF8.to_le_bytes())?;
+        w.write_all(&self.method.to_le_bytes())?;
+        w.write_all(&Self::TIME.to_le_bytes())?;
+        w.write_all(&Self::DATE.to_le_bytes())?;
+        w.write_all(&self.crc.to_le_bytes())?;
+        w.write_all(&clamp_u32(self.compressed).to_le_bytes())?;
+        w.write_all(&clamp_u32(self.uncompressed).to_le_bytes())?;
+        w.write_all(&(self.name.len() as u16).to_le_bytes())?;
+        w.write_all(&(if extra.is_empty() { 0 } else { extra.len() as u16 + 4 }).to_le_bytes())?;
+        // No comment, on disk 0, no attributes.
+        w.write_all(&[0; 10])?;
+        w.write_all(&clamp_u32(self.offset).to_le_bytes())?;
+        w.write_all(self.name.as_bytes())?;
+        if !extra.is_empty() {
+            w.write_all(&1u16.to_le_bytes())?;
+            w.write_all(&(extra.len() as u16).to_le_bytes())?;
+            w.write_all(&extra)?;
+        }
+        Ok(())
+    }
+
+    // Version of the zip format needed to extract the entry: 2.0 for
+    // deflate, or 4.5 for zip64.
+    fn version(&self) -> u16 {
+        if self.zip64 || self.offset >= 0xffff_ffff {
+            45
+        } else {
+            20
+        }
+    }
+}
+
+// Value for a 32-bit field of a zip header, or 0xffffffff if it does not fit
+// and is in a zip64 record instead.
+fn clamp_u32(value: u64) -> u32 {
+    value.min(0xffff_ffff) as u32
+}
+
+// Value for a 32-bit field of a local header without zip64 extensions.
+fn to_u32(value: u64) -> std::io::Result<u32> {
+    u32::try_from(value)
+        .ok()
+        .filter(|&value| value != 0xffff_ffff)
+        .ok_or_else(|| std::io::Error::new(ErrorKind::InvalidData, "Array is too large for a zip archive without zip64 extensions."))
+}
+
+// Destination of the npy file of an entry, compressed or not.
+enum Encoder<W: Write> {
+    Stored(W),
+    Deflate(DeflateEncoder<W>),
+}
+impl<W: Write> Encoder<W> {
+    // Write any remaining compressed data.
+    fn finish(self) -> std::io::Result<()> {
+        match self {
+            Encoder::Stored(mut w) => w.flush(),
+            Encoder::Deflate(encoder) => encoder.finish()?.flush(),
+        }
+    }
+}
+impl<W: Write> Write for Encoder<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self {
+            Encoder::Stored(w) => w.write(buf),
+            Encoder::Deflate(encoder) => encoder.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            Encoder::Stored(w) => w.flush(),
+            Encoder::Deflate(encoder) => encoder.flush(),
+        }
+    }
+}
+
+// Writer which keeps the CRC-32 and the number of bytes of the data written
+// through it, before compression.
+struct Counter<W: Write> {
+    inner: Encoder<W>,
+    crc: Crc,
+    n_bytes: u64,
+}
+impl<W: Write> Counter<W> {
+    fn new(inner: Encoder<W>) -> Self {
+        Self {
+            inner,
+            crc: Crc::new(),
+            n_bytes: 0,
+        }
+    }
+
+    // Finish writing, returning the CRC-32 and the number of bytes.
+    fn finish(self) -> std::io::Result<(u32, u64)> {
+        self.inner.finish()?;
+        Ok((self.crc.sum(), self.n_bytes))
+    }
+}
+impl<W: Write> Write for Counter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.crc.update(&buf[..n]);
+        self.n_bytes += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+// Reader for npz files, which tolerates numpy's naming of the arrays.
+pub(crate) struct NpzSource<R: Read + Seek> {
+    npz: NpzReader<R>,
+    // Names of the entries in the zip archive
+    names: Vec<String>,
+}
+impl<R: Read + Seek> NpzSource<R> {
+    // Read the header of an npz file.
+    pub(crate) fn new(r: R) -> Result<Self, Error> {
+        let mut npz = NpzReader::new(r)?;
+        let names = npz.names()?;
+        Ok(Self { npz, names })
+    }
+
+    // Name of the entry in the zip archive holding the named array. If there
+    // is no such entry, the name itself, so that reading it reports a missing
+    // file.
+    fn entry<'a>(&'a self, name: &'a str) -> &'a str {
+        self.names.iter().find(|entry| entry_matches(entry, name)).map_or(name, |entry| entry.as_str())
+    }
+}
+impl<R: Read + Seek> ArraySource for NpzSource<R> {
+    fn has(&self, name: &str) -> bool {
+        self.names.iter().any(|entry| entry_matches(entry, name))
+    }
+
+    fn read<T, D>(&mut self, name: &str) -> Result<Option<Array<T, D>>, Error>
+    where
+        T: ReadableElement + MatElement + H5Element,
+        D: Dimension,
+    {
+        let entry = self.entry(name).to_string();
+        match self.npz.by_name(&entry) {
+            Ok(array) => Ok(Some(array)),
+            Err(ReadNpzError::Npy(ReadNpyError::WrongDescriptor(_))) => Ok(None),
+            Err(err) => Err(Error::read_npz(name)(err)),
+        }
+    }
+}
+
+// Whether an entry in an npz file holds the named array. Numpy appends .npy
+// to the name of each array in the archive, but this crate does not.
+fn entry_matches(entry: &str, name: &str) -> bool {
+    entry == name || entry.strip_suffix(".npy") == Some(name)
+}
+
+// Directory of npy files, one per array, named after the array with a .npy
+// suffix.
+pub(crate) struct NpyDir {
+    dir: PathBuf,
+}
+impl NpyDir {
+    // Directory to save arrays to, created if it does not exist yet.
+    pub(crate) fn create(dir: &Path) -> Result<Self, Error> {
+        std::fs::create_dir_all(dir).map_err(|err| Error::io(dir, err))?;
+        Ok(Self { dir: dir.to_path_buf() })
+    }
+
+    // Existing directory to load arrays from.
+    pub(crate) fn open(dir: &Path) -> Result<Self, Error> {
+        std::fs::read_dir(dir).map_err(|err| Error::io(dir, err))?;
+        Ok(Self { dir: dir.to_path_buf() })
+    }
+
+    // Remove the npy files holding the named arrays, if they exist.
+    pub(crate) fn remove(&mut self, names: &[&str]) -> Result<(), Error> {
+        for name in names {
+            let path = self.path(name);
+            match std::fs::remove_file(&path) {
+                Err(err) if err.kind() != ErrorKind::NotFound => return Err(Error::io(&path, err)),
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    // Path of the npy file holding the named array.
+    fn path(&self, name: &str) -> PathBuf {
+        self.dir.join(format!("{}.npy", name))
+    }
+}
+impl ArraySink for NpyDir {
+    fn add_array<S, D>(&mut self, name: &str, array: &ArrayBase<S, D>) -> Result<(), Error>
+    where
+        S: Data,
+        S::Elem: WritableElement + MatElement + H5Element,
+        D: Dimension,
+    {
+        let path = self.path(name);
+        let file = File::create(&path).map_err(|err| Error::io(&path, err))?;
+        array
+            .write_npy(BufWriter::new(file))
+            .map_err(|source| Error::WriteNpy { path, source })
+    }
+}
+impl ArraySource for NpyDir {
+    fn has(&self, name: &str) -> bool {
+        self.path(name).is_file()
+    }
+
+    fn read<T, D>(&mut self, name: &str) -> Result<Option<Array<T, D>>, Error>
+    where
+        T: ReadableElement + MatElement + H5Element,
+        D: Dimension,
+    {
+        let path = self.path(name);
+        let file = File::open(&path).map_err(|err| Error::io(&path, err))?;
+        match Array::read_npy(BufReader::new(file)) {
+            Ok(array) => Ok(Some(array)),
+            Err(ReadNpyError::WrongDescriptor(_)) => Ok(None),
+            Err(source) => Err(Error::ReadNpy { path, source }),
+        }
+    }
+}
+impl<W: Write> ArraySink for MatWriter<W> {
+    fn add_array<S, D>(&mut self, name: &str, array: &ArrayBase<S, D>) -> Result<(), Error>
+    where
+        S: Data,
+        S::Elem: WritableElement + MatElement + H5Element,
+        D: Dimension,
+    {
+        MatWriter::add_array(self, name, array)
+    }
+}
+// Sink which only checks that every array fits in a MAT-file, so that a file
+// is not left half written by an array that does not.
+pub(crate) struct MatSizes;
+impl ArraySink for MatSizes {
+    fn add_array<S, D>(&mut self, name: &str, array: &ArrayBase<S, D>) -> Result<(), Error>
+    where
+        S: Data,
+        S::Elem: WritableElement + MatElement + H5Element,
+        D: Dimension,
+    {
+        mat::check_size::<S::Elem>(name, array.len())?;
+        Ok(())
+    }
+}
+impl ArraySource for MatReader {
+    fn has(&self, name: &str) -> bool {
+        self.names().contains(&name)
+    }
+
+    fn read<T, D>(&mut self, name: &str) -> Result<Option<Array<T, D>>, Error>
+    where
+        T: ReadableElement + MatElement + H5Element,
+        D: Dimension,
+    {
+        Ok(self.try_by_name(name)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ndarray::{array, Ix1, Ix2};
+    use std::io::Cursor;
+
+    fn x() -> Array<f64, Ix2> {
+        Array::from_shape_fn((40, 30), |(i, j)| ((i * 30 + j) % 7) as f64)
+    }
+
+    // Write two arrays to an npz file in memory.
+    fn npz_bytes(compression: Compression) -> Vec<u8> {
+        let mut bytes = Cursor::new(Vec::new());
+        let mut npz = NpzWriter::new(&mut bytes, compression).unwrap();
+        npz.add_array("x", &x()).unwrap();
+        npz.add_array("ids", &array![0u64, 0, 1, 2]).unwrap();
+        npz.finish().unwrap();
+        bytes.into_inner()
+    }
+
+    #[test]
+    fn arrays_round_trip_at_every_level() {
+        let stored = npz_bytes(Compression::Stored);
+        for level in [None, Some(0), Some(1), Some(9)] {
+            let compression = level.map_or(Compression::Stored, |level| Compression::Deflate { level });
+            let bytes = npz_bytes(compression);
+            if level == Some(9) {
+                assert!(bytes.len() < stored.len() / 4, "{} of {} bytes", bytes.len(), stored.len());
+            }
+            let mut npz = NpzReader::new(Cursor::new(bytes)).unwrap();
+            assert_eq!(npz.names().unwrap(), ["x", "ids"]);
+            let read_x: Array<f64, Ix2> = npz.by_name("x").unwrap();
+            let read_ids: Array<u64, Ix1> = npz.by_name("ids").unwrap();
+            assert_eq!(read_x, x());
+            assert_eq!(read_ids, array![0, 0, 1, 2]);
+        }
+    }
+
+    #[test]
+    fn same_arrays_give_same_file() {
+        let compression = Compression::Deflate { level: 6 };
+        assert_eq!(npz_bytes(compression), npz_bytes(compression));
+    }
+
+    #[test]
+    fn invalid_compression_is_an_error() {
+        let compression = Compression::Deflate { level: 10 };
+        match NpzWriter::new(Cursor::new(Vec::new()), compression) {
+            Err(Error::InvalidCompression(invalid)) => assert_eq!(invalid, compression),
+            Err(err) => panic!("unexpected error: {}", err),
+            Ok(_) => panic!("invalid compression was accepted"),
+        }
+    }
+}
@@ -0,0 +1,430 @@
+//! Sign-flipping and permutation tests with exchangeability blocks.
+//!
+//! When the errors are symmetric, flipping the sign of the residuals is an
+//! alternative to the wild bootstrap that is standard in neuroimaging (as in
+//! PALM). The block ids serve as exchangeability blocks: observations may be
+//! sign-flipped or permuted only in ways that preserve the dependence within
+//! each block. Nested blocks are described by an [`ExchangeabilityTree`].
+//!
+//! Flipping the signs of whole blocks, see
+//! [`Shuffle::WholeBlock`](crate::exchange::Shuffle::WholeBlock), is valid
+//! whatever the dependence within each block. Flipping the signs of
+//! observations individually, see
+//! [`Shuffle::WithinBlock`](crate::exchange::Shuffle::WithinBlock), assumes
+//! that the errors within a block are independent.
+//!
+//! Example, flipping the signs of whole blocks:
+//! ```no_run
+//! use swe_mockup::{MockData, MockParams};
+//! use swe_mockup::exchange::Shuffle;
+//! use swe_mockup::maxstat::Tail;
+//! use swe_mockup::permute::{PermutationScheme, PermutationTest};
+//! use swe_mockup::swe;
+//! let mock_data = MockData::<f64>::from_params(MockParams::default());
+//! let blocks = swe::block_indices(mock_data.block_ids.view());
+//! let stat = |y: ndarray::ArrayView2<f64>| swe::t_stat(mock_data.x_pinv.view(), y, &blocks, 0);
+//! let observed = stat(mock_data.resid.view());
+//! let mut test = PermutationTest::new(
+//!     mock_data.exchangeability_tree(Shuffle::WholeBlock),
+//!     PermutationScheme::default(),
+//!     observed,
+//!     Tail::Both,
+//!     42,
+//! )?;
+//! test.run(1000, mock_data.resid.view(), stat);
+//! let p_fwer = test.p_fwer();
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+
+use crate::checkpoint::{read_rng, write_rng, Checkpoint, CheckpointError};
+use crate::exchange::{ExchangeabilityError, ExchangeabilityTree};
+use crate::maxstat::{MaxStat, Tail};
+use ndarray::{Array, ArrayView, Axis, Dim};
+use ndarray_npy::{NpzReader, NpzWriter, ReadableElement, WritableElement, WriteNpzError};
+use num_traits::Float;
+use std::io::{Read, Seek, Write};
+use rand::SeedableRng;
+use rand_chacha::ChaCha8Rng;
+
+/// Which resampling operations to perform.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PermutationScheme {
+    /// Randomly flip the signs of the residuals.
+    pub sign_flip: bool,
+    /// Randomly permute the residuals.
+    pub permute: bool,
+}
+impl Default for PermutationScheme {
+    fn default() -> Self {
+        Self {
+            sign_flip: true,
+            permute: false,
+        }
+    }
+}
+impl std::fmt::Display for PermutationScheme {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match (self.sign_flip, self.permute) {
+            (true, true) => write!(f, "Sign flips and permutations"),
+            (true, false) => write!(f, "Sign flips"),
+            (false, true) => write!(f, "Permutations"),
+            (false, false) => write!(f, "No resampling"),
+        }
+    }
+}
+
+/// Error constructing a permutation test.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum PermuteError {
+    /// Neither sign flipping nor permutation was requested.
+    NoResampling,
+    /// The exchangeability blocks do not allow the requested resampling.
+    Exchangeability(ExchangeabilityError),
+}
+impl std::fmt::Display for PermuteError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            PermuteError::NoResampling => {
+                write!(f, "Neither sign flipping nor permutation was requested.")
+            }
+            PermuteError::Exchangeability(err) => err.fmt(f),
+        }
+    }
+}
+impl std::error::Error for PermuteError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            PermuteError::NoResampling => None,
+            PermuteError::Exchangeability(err) => Some(err),
+        }
+    }
+}
+impl From<ExchangeabilityError> for PermuteError {
+    fn from(err: ExchangeabilityError) -> Self {
+        PermuteError::Exchangeability(err)
+    }
+}
+
+/// A single resampling of the observations.
+///
+/// Row `i` of the resampled data is row `order[i]` of the original data,
+/// with its sign flipped if `flip[i]` is true.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Resample {
+    /// Source observation for each row.
+    pub order: Vec<usize>,
+    /// Whether to flip the sign of each row.
+    pub flip: Vec<bool>,
+}
+impl Resample {
+    /// Resampling which leaves the data unchanged.
+    pub fn identity(n_obs: usize) -> Self {
+        Self {
+            order: (0..n_obs).collect(),
+            flip: vec![false; n_obs],
+        }
+    }
+
+    /// Apply to an observations x features data matrix.
+    pub fn apply<S>(&self, data: ArrayView<S, Dim<[usize; 2]>>) -> Array<S, Dim<[usize; 2]>>
+    where
+        S: Float,
+    {
+        let mut resampled = data.select(Axis(0), &self.order);
+        for (mut row, &flip) in resampled.axis_iter_mut(Axis(0)).zip(self.flip.iter()) {
+            if flip {
+                row.mapv_inplace(|x| -x);
+            }
+        }
+        resampled
+    }
+}
+
+/// Sign-flipping and/or permutation test of a statistic computed for each
+/// feature.
+///
+/// Keeps counts of how often each feature's resampled statistic is at least
+/// as extreme as the observed statistic for uncorrected p-values, along with
+/// the max-statistic distribution for FWER-corrected p-values. Resamplings
+/// are drawn from a seeded, portable random number generator, so the same
+/// seed always gives the same sequence of resamplings.
+#[derive(Clone, Debug)]
+pub struct PermutationTest<S> {
+    // Exchangeability blocks.
+    tree: ExchangeabilityTree,
+    // Which resampling operations to perform.
+    scheme: PermutationScheme,
+    // Random number generator.
+    rng: ChaCha8Rng,
+    // Max-statistic distribution, which also holds the observed statistics.
+    max_stat: MaxStat<S>,
+    // Number of replicates at least as extreme as observed for each feature.
+    exceed: Array<u64, Dim<[usize; 1]>>,
+}
+impl<S> PermutationTest<S>
+where
+    S: Float,
+{
+    /// Make a new permutation test using the exchangeability blocks in
+    /// `tree`, given the `observed` statistic for each feature. The `seed`
+    /// initializes the random number generator.
+    pub fn new(
+        tree: ExchangeabilityTree,
+        scheme: PermutationScheme,
+        observed: Array<S, Dim<[usize; 1]>>,
+        tail: Tail,
+        seed: u64,
+    ) -> Result<Self, PermuteError> {
+        if !scheme.sign_flip && !scheme.permute {
+            return Err(PermuteError::NoResampling);
+        }
+        // Blocks can only be exchanged as whole units if they have the same
+        // structure.
+        if scheme.permute {
+            tree.check_permutable()?;
+        }
+
+        let exceed = Array::zeros(observed.len());
+        Ok(Self {
+            tree,
+            scheme,
+            rng: ChaCha8Rng::seed_from_u64(seed),
+            max_stat: MaxStat::new(observed, tail),
+            exceed,
+        })
+    }
+
+    /// Draw the next random resampling.
+    pub fn sample(&mut self) -> Resample {
+        let (order, flip) = self.tree.sample(self.scheme.sign_flip, self.scheme.permute, &mut self.rng);
+        Resample { order, flip }
+    }
+
+    /// Add the resampled statistic for each feature from one replicate.
+    ///
+    /// Panics if the number of statistics differs from the number of
+    /// features.
+    pub fn add_replicate(&mut self, stats: ArrayView<S, Dim<[usize; 1]>>) {
+        self.max_stat.add_replicate(stats);
+        let tail = self.max_stat.tail();
+        let observed = self.max_stat.observed();
+        ndarray::Zip::from(&mut self.exceed)
+            .and(&stats)
+            .and(&observed)
+            .for_each(|exceed, &stat, &observed| {
+                if tail.orient(stat) >= tail.orient(observed) {
+                    *exceed += 1;
+                }
+            });
+    }
+
+    /// Run `n_rep` replicates, resampling the rows of the observations x
+    /// features `data` and computing the resampled statistic for each feature
+    /// with `stat`.
+    ///
+    /// Panics if `data` does not have one row per observation.
+    pub fn run<F>(&mut self, n_rep: usize, data: ArrayView<S, Dim<[usize; 2]>>, mut stat: F)
+    where
+        F: FnMut(ArrayView<S, Dim<[usize; 2]>>) -> Array<S, Dim<[usize; 1]>>,
+    {
+        assert_eq!(
+            data.len_of(Axis(0)),
+            self.tree.n_obs(),
+            "Data has {} rows but there are {} observations.",
+            data.len_of(Axis(0)),
+            self.tree.n_obs()
+        );
+        for _ in 0..n_rep {
+            let resampled = self.sample().apply(data);
+            let stats = stat(resampled.view());
+            self.add_replicate(stats.view());
+        }
+    }
+
+    /// Run replicates as in [`PermutationTest::run()`] until a total of
+    /// `n_total` replicates have been run, writing a checkpoint every
+    /// `checkpoint.interval` replicates and upon completion.
+    ///
+    /// A test resumed with [`PermutationTest::from_checkpoint()`] continues
+    /// with exactly the same resamplings it would have drawn had it not been
+    /// interrupted.
+    pub fn run_checkpointed<F>(
+        &mut self,
+        n_total: usize,
+        data: ArrayView<S, Dim<[usize; 2]>>,
+        mut stat: F,
+        checkpoint: &Checkpoint,
+    ) -> Result<(), CheckpointError>
+    where
+        S: WritableElement,
+        F: FnMut(ArrayView<S, Dim<[usize; 2]>>) -> Array<S, Dim<[usize; 1]>>,
+    {
+        while self.n_replicates() < n_total {
+            let n_rep = std::cmp::min(checkpoint.interval.get(), n_total - self.n_replicates());
+            self.run(n_rep, data, &mut stat);
+            checkpoint.write(|w| self.save_checkpoint(w))?;
+        }
+        Ok(())
+    }
+
+    /// Number of replicates run so far.
+    pub fn n_replicates(&self) -> usize {
+        self.max_stat.n_replicates()
+    }
+
+    /// Exchangeability blocks.
+    pub fn tree(&self) -> &ExchangeabilityTree {
+        &self.tree
+    }
+
+    /// Which resampling operations are performed.
+    pub fn scheme(&self) -> PermutationScheme {
+        self.scheme
+    }
+
+    /// Max-statistic distribution of the replicates so far.
+    pub fn max_stat(&self) -> &MaxStat<S> {
+        &self.max_stat
+    }
+
+    /// Uncorrected permutation p-value for each feature.
+    ///
+    /// The observed statistic is counted as one of the replicates, so the
+    /// smallest possible p-value is `1 / (n_replicates + 1)`.
+    pub fn p_uncorrected(&self) -> Array<f64, Dim<[usize; 1]>> {
+        let denom = (self.n_replicates() + 1) as f64;
+        let mut p = self.exceed.mapv(|exceed| (exceed + 1) as f64 / denom);
+        // Features with no observed statistic have no p-value.
+        p.zip_mut_with(&self.max_stat.observed(), |p, observed| {
+            if observed.is_nan() {
+                *p = f64::NAN;
+            }
+        });
+        p
+    }
+
+    /// Westfall-Young step-down FWER-corrected p-value for each feature, see
+    /// [`MaxStat::p_step_down()`].
+    pub fn p_fwer(&self) -> Array<f64, Dim<[usize; 1]>> {
+        self.max_stat.p_step_down()
+    }
+}
+impl<S> PermutationTest<S>
+where
+    S: Float + WritableElement,
+{
+    /// Save a checkpoint of the complete state of the test, except for the
+    /// exchangeability tree, to an npz file.
+    pub fn save_checkpoint<W: Seek + Write>(&self, w: W) -> Result<(), WriteNpzError> {
+        let mut npz = NpzWriter::new(w);
+        write_rng(&mut npz, &self.rng)?;
+        npz.add_array("n_obs", &Array::from_elem((1,), self.tree.n_obs() as u64))?;
+        npz.add_array("scheme", &Array::from(vec![self.scheme.sign_flip as u8, self.scheme.permute as u8]))?;
+        npz.add_array("exceed", &self.exceed)?;
+        self.max_stat.write_npz(&mut npz)?;
+        npz.finish()?;
+        Ok(())
+    }
+}
+impl<S> PermutationTest<S>
+where
+    S: Float + ReadableElement,
+{
+    /// Resume a test from a checkpoint saved by
+    /// [`PermutationTest::save_checkpoint()`]. The `tree` must be the same
+    /// exchangeability tree the test was constructed with.
+    pub fn from_checkpoint<R: Seek + Read>(r: R, tree: ExchangeabilityTree) -> Result<Self, CheckpointError> {
+        let mut npz = NpzReader::new(r)?;
+        let rng = read_rng(&mut npz)?;
+        let n_obs: Array<u64, Dim<[usize; 1]>> = npz.by_name("n_obs")?;
+        let scheme: Array<u8, Dim<[usize; 1]>> = npz.by_name("scheme")?;
+        let exceed: Array<u64, Dim<[usize; 1]>> = npz.by_name("exceed")?;
+        let max_stat = MaxStat::read_npz(&mut npz)?;
+
+        // Make sure the checkpoint belongs to this test.
+        if n_obs.first().copied() != Some(tree.n_obs() as u64) {
+            return Err(CheckpointError::Invalid(format!(
+                "Checkpoint does not have {} observations like the exchangeability tree.",
+                tree.n_obs()
+            )));
+        }
+        if exceed.len() != max_stat.observed().len() {
+            return Err(CheckpointError::Invalid(format!(
+                "Have {} exceedance counters but {} features.",
+                exceed.len(),
+                max_stat.observed().len()
+            )));
+        }
+        let scheme = match scheme.as_slice() {
+            Some(&[sign_flip, permute]) => PermutationScheme {
+                sign_flip: sign_flip != 0,
+                permute: permute != 0,
+            },
+            _ => {
+                return Err(CheckpointError::Invalid("Unknown permutation scheme.".to_string()));
+            }
+        };
+        if scheme.permute {
+            tree.check_permutable()
+                .map_err(|err| CheckpointError::Invalid(err.to_string()))?;
+        }
+
+        Ok(Self {
+            tree,
+            scheme,
+            rng,
+            max_stat,
+            exceed,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::exchange::Shuffle;
+    use std::fs::File;
+    use std::num::NonZeroUsize;
+
+    // Make a test of column sums over 4 blocks of 3 observations.
+    fn column_sums_test(data: ArrayView<f64, Dim<[usize; 2]>>) -> PermutationTest<f64> {
+        let tree = ExchangeabilityTree::flat(Array::from_iter((0..12).map(|obs| obs / 3)), Shuffle::WithinBlock);
+        let scheme = PermutationScheme {
+            sign_flip: true,
+            permute: true,
+        };
+        PermutationTest::new(tree, scheme, data.sum_axis(Axis(0)), Tail::Both, 42).unwrap()
+    }
+
+    #[test]
+    fn resumed_test_matches_uninterrupted_test() {
+        let data = Array::from_shape_fn((12, 5), |(obs, feat)| ((obs * 5 + feat) as f64).sin());
+        let stat = |y: ArrayView<f64, Dim<[usize; 2]>>| y.sum_axis(Axis(0));
+        let n_total = 40;
+
+        let mut straight = column_sums_test(data.view());
+        straight.run(n_total, data.view(), stat);
+
+        // Run half the replicates with checkpoints at an interval that does
+        // not divide the total, then resume from the last checkpoint.
+        let checkpoint = Checkpoint {
+            path: std::env::temp_dir().join(format!("swe-mockup-checkpoint-{}.npz", std::process::id())),
+            interval: NonZeroUsize::new(7).unwrap(),
+        };
+        let mut first = column_sums_test(data.view());
+        first.run_checkpointed(n_total / 2, data.view(), stat, &checkpoint).unwrap();
+        let tree = first.tree().clone();
+        drop(first);
+        let mut resumed = PermutationTest::<f64>::from_checkpoint(File::open(&checkpoint.path).unwrap(), tree).unwrap();
+        std::fs::remove_file(&checkpoint.path).unwrap();
+        assert_eq!(resumed.n_replicates(), n_total / 2);
+        resumed.run(n_total - n_total / 2, data.view(), stat);
+
+        assert_eq!(resumed.n_replicates(), straight.n_replicates());
+        assert_eq!(resumed.max_stat().max(), straight.max_stat().max());
+        assert_eq!(resumed.max_stat().min(), straight.max_stat().min());
+        assert_eq!(resumed.max_stat().p_step_down(), straight.max_stat().p_step_down());
+        assert_eq!(resumed.exceed, straight.exceed);
+        assert_eq!(resumed.rng.get_word_pos(), straight.rng.get_word_pos());
+    }
+}
@@ -0,0 +1,510 @@
+//! Covariates and cluster labels from CSV or TSV phenotype tables.
+//!
+//! Phenotype tables have one row per subject with a subject id and columns
+//! such as site, family id, age and sex. The rows are usually in a different
+//! order from the observations of the residual matrix and may include
+//! subjects without imaging data, so [`PhenotypeTable::align()`] matches them
+//! up by subject id. Every observation must have exactly one row in the table;
+//! unmatched observations and duplicated subjects are reported as errors, and
+//! rows of subjects without an observation are listed by
+//! [`Aligned::unused()`].
+//!
+//! The aligned table gives the block ids from a column of cluster labels, see
+//! [`Aligned::block_ids()`], and a design matrix from named covariates, see
+//! [`Aligned::design_matrix()`].
+//!
+//! Example:
+//! ```
+//! use swe_mockup::phenotype::{Covariate, PhenotypeTable};
+//! let csv = "subject,site,family,age,sex
+//! s1,A,f1,10.5,M
+//! s2,B,f2,9.0,F
+//! s3,A,f1,11.0,F
+//! s4,B,f3,10.0,M
+//! ";
+//! let table = PhenotypeTable::from_reader(csv.as_bytes(), b',', "subject")?;
+//! let aligned = table.align(&["s3", "s1", "s2"])?;
+//! assert_eq!(aligned.unused(), vec!["s4"]);
+//! let (block_ids, labels) = aligned.block_ids("family")?;
+//! assert_eq!(block_ids.view().to_vec(), vec![0, 0, 1]);
+//! assert_eq!(labels.labels(), ["f1", "f2"]);
+//! let design = aligned.design_matrix(
+//!     &[Covariate::Categorical("sex".to_string()), Covariate::Continuous("age".to_string())],
+//!     true,
+//! )?;
+//! assert_eq!(design.names, ["intercept", "sex_M", "age"]);
+//! assert_eq!(design.x.row(1).to_vec(), vec![1., 1., 10.5]);
+//! # Ok::<(), swe_mockup::Error>(())
+//! ```
+
+use crate::blocks::{BlockIds, BlockLabels};
+use crate::Error;
+use ndarray::{Array, Dim};
+use std::collections::{BTreeSet, HashMap};
+use std::io::Read;
+use std::path::Path;
+
+// Largest number of subject ids listed in an error message.
+const MAX_LISTED: usize = 10;
+
+/// Error reading or aligning a phenotype table.
+#[derive(Debug)]
+pub enum PhenotypeError {
+    /// The table is not valid CSV, e.g. rows have different numbers of
+    /// fields.
+    Csv(csv::Error),
+    /// There is no column with this name.
+    Column {
+        /// Name of the column
+        name: String,
+    },
+    /// Subjects have more than one row in the table.
+    DuplicateSubjects {
+        /// Ids of the duplicated subjects
+        ids: Vec<String>,
+    },
+    /// Subjects have more than one observation.
+    DuplicateObservations {
+        /// Ids of the duplicated subjects
+        ids: Vec<String>,
+    },
+    /// Observations have no row in the table.
+    Unmatched {
+        /// Ids of the unmatched subjects
+        ids: Vec<String>,
+    },
+    /// A value is missing, i.e. empty or `NA`.
+    Missing {
+        /// Id of the subject
+        subject: String,
+        /// Name of the column
+        column: String,
+    },
+    /// A value of a continuous covariate is not a finite number.
+    NotNumeric {
+        /// Id of the subject
+        subject: String,
+        /// Name of the column
+        column: String,
+        /// The offending value
+        value: String,
+    },
+}
+impl std::fmt::Display for PhenotypeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            PhenotypeError::Csv(err) => write!(f, "Error reading phenotype table: {}", err),
+            PhenotypeError::Column { name } => write!(f, "Phenotype table has no column {}.", name),
+            PhenotypeError::DuplicateSubjects { ids } => {
+                write!(f, "Subjects with more than one row in the phenotype table ({}): {}", ids.len(), list(ids))
+            }
+            PhenotypeError::DuplicateObservations { ids } => {
+                write!(f, "Subjects with more than one observation ({}): {}", ids.len(), list(ids))
+            }
+            PhenotypeError::Unmatched { ids } => {
+                write!(f, "Observations without a row in the phenotype table ({}): {}", ids.len(), list(ids))
+            }
+            PhenotypeError::Missing { subject, column } => {
+                write!(f, "Subject {} has a missing value of {}.", subject, column)
+            }
+            PhenotypeError::NotNumeric { subject, column, value } => {
+                write!(f, "Subject {} has a value of {} which is not a finite number: {}", subject, column, value)
+            }
+        }
+    }
+}
+impl std::error::Error for PhenotypeError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            PhenotypeError::Csv(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+impl From<csv::Error> for PhenotypeError {
+    fn from(err: csv::Error) -> Self {
+        PhenotypeError::Csv(err)
+    }
+}
+
+// List subject ids for an error message, eliding all but the first few.
+fn list(ids: &[String]) -> String {
+    let listed = ids.iter().take(MAX_LISTED).map(String::as_str).collect::<Vec<_>>().join(", ");
+    if ids.len() > MAX_LISTED {
+        format!("{}, and {} more", listed, ids.len() - MAX_LISTED)
+    } else {
+        listed
+    }
+}
+
+/// Covariate for a column of the design matrix.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Covariate {
+    /// Numeric column, e.g. age, used as is.
+    Continuous(String),
+    /// Column of categories, e.g. sex or site, coded as a dummy (indicator)
+    /// variable for each category except the first in sorted order.
+    Categorical(String),
+}
+impl Covariate {
+    /// Name of the column.
+    pub fn name(&self) -> &str {
+        match self {
+            Covariate::Continuous(name) | Covariate::Categorical(name) => name,
+        }
+    }
+}
+
+/// Design matrix built from a phenotype table.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Design {
+    /// Observations x predictors design matrix
+    pub x: Array<f64, Dim<[usize; 2]>>,
+    /// Name of each predictor: `intercept`, the name of a continuous
+    /// covariate, or the name of a categorical covariate and a category
+    /// joined by an underscore, e.g. `site_B`
+    pub names: Vec<String>,
+}
+
+/// Table of phenotypes with one row per subject.
+#[derive(Clone, Debug)]
+pub struct PhenotypeTable {
+    // Names of the columns
+    headers: Vec<String>,
+    // Id of the subject of each row
+    subjects: Vec<String>,
+    // Row of each subject id
+    rows: HashMap<String, usize>,
+    // Fields of each row
+    records: Vec<csv::StringRecord>,
+}
+impl PhenotypeTable {
+    /// Read a table with a header row, with fields separated by `delimiter`,
+    /// e.g. `b','` for CSV or `b'\t'` for TSV. Subjects are identified by the
+    /// column named `subject_column`. Whitespace around fields is ignored.
+    /// Returns an error if there is no such column or any subject has more
+    /// than one row.
+    pub fn from_reader<R: Read>(r: R, delimiter: u8, subject_column: &str) -> Result<Self, Error> {
+        let mut reader = csv::ReaderBuilder::new()
+            .delimiter(delimiter)
+            .trim(csv::Trim::All)
+            .from_reader(r);
+        let headers: Vec<String> = reader.headers().map_err(PhenotypeError::from)?.iter().map(String::from).collect();
+        let subject = headers
+            .iter()
+            .position(|header| header == subject_column)
+            .ok_or_else(|| PhenotypeError::Column { name: subject_column.to_string() })?;
+
+        // Read the rows, keeping track of duplicated subjects.
+        let mut subjects = Vec::new();
+        let mut rows = HashMap::new();
+        let mut records = Vec::new();
+        let mut duplicates = BTreeSet::new();
+        for record in reader.records() {
+            let record = record.map_err(PhenotypeError::from)?;
+            let id = record[subject].to_string();
+            if rows.insert(id.clone(), records.len()).is_some() {
+                duplicates.insert(id.clone());
+            }
+            subjects.push(id);
+            records.push(record);
+        }
+        if !duplicates.is_empty() {
+            return Err(PhenotypeError::DuplicateSubjects { ids: duplicates.into_iter().collect() }.into());
+        }
+        Ok(Self {
+            headers,
+            subjects,
+            rows,
+            records,
+        })
+    }
+
+    /// Read a table from a file. Files ending in `.tsv` or `.tab` are read as
+    /// tab-separated, anything else as comma-separated, see
+    /// [`from_reader()`](PhenotypeTable::from_reader).
+    pub fn from_path<P: AsRef<Path>>(path: P, subject_column: &str) -> Result<Self, Error> {
+        let path = path.as_ref();
+        let delimiter = match path.extension().and_then(|ext| ext.to_str()) {
+            Some("tsv" | "tab") => b'\t',
+            _ => b',',
+        };
+        let file = std::fs::File::open(path).map_err(|err| Error::io(path, err))?;
+        Self::from_reader(std::io::BufReader::new(file), delimiter, subject_column)
+    }
+
+    /// Names of the columns.
+    pub fn headers(&self) -> &[String] {
+        &self.headers
+    }
+
+    /// Subject id of each row, in the order of the file.
+    pub fn subjects(&self) -> &[String] {
+        &self.subjects
+    }
+
+    /// Match the rows of the table to observations, given the subject id of
+    /// each observation, i.e. of each row of the residual matrix. Returns an
+    /// error listing the subjects if any observation has no row in the table
+    /// or any subject has more than one observation.
+    pub fn align<T: AsRef<str>>(&self, observations: &[T]) -> Result<Aligned<'_>, Error> {
+        let mut rows = Vec::with_capacity(observations.len());
+        let mut unmatched = Vec::new();
+        let mut seen = vec![false; self.records.len()];
+        let mut duplicates = BTreeSet::new();
+        for id in observations {
+            let id = id.as_ref();
+            match self.rows.get(id) {
+                Some(&row) => {
+                    if std::mem::replace(&mut seen[row], true) {
+                        duplicates.insert(id.to_string());
+                    }
+                    rows.push(row);
+                }
+                None => unmatched.push(id.to_string()),
+            }
+        }
+        if !unmatched.is_empty() {
+            return Err(PhenotypeError::Unmatched { ids: unmatched }.into());
+        }
+        if !duplicates.is_empty() {
+            return Err(PhenotypeError::DuplicateObservations { ids: duplicates.into_iter().collect() }.into());
+        }
+        Ok(Aligned { table: self, rows })
+    }
+
+    // Index of the named column.
+    fn column(&self, name: &str) -> Result<usize, PhenotypeError> {
+        self.headers
+            .iter()
+            .position(|header| header == name)
+            .ok_or_else(|| PhenotypeError::Column { name: name.to_string() })
+    }
+}
+
+/// Rows of a phenotype table in the order of the observations, see
+/// [`PhenotypeTable::align()`].
+#[derive(Clone, Debug)]
+pub struct Aligned<'a> {
+    // The table
+    table: &'a PhenotypeTable,
+    // Row of the table of each observation
+    rows: Vec<usize>,
+}
+impl Aligned<'_> {
+    /// Number of observations.
+    pub fn n_obs(&self) -> usize {
+        self.rows.len()
+    }
+
+    /// Subjects in the table without an observation, in the order of the
+    /// table. Their rows are ignored.
+    pub fn unused(&self) -> Vec<&str> {
+        let mut used = vec![false; self.table.records.len()];
+        for &row in &self.rows {
+            used[row] = true;
+        }
+        self.table
+            .subjects
+            .iter()
+            .zip(used)
+            .filter(|(_, used)| !used)
+            .map(|(subject, _)| subject.as_str())
+            .collect()
+    }
+
+    /// Values of the named column for each observation. Returns an error if
+    /// there is no such column or any value is missing, i.e. empty or `NA`.
+    pub fn column(&self, name: &str) -> Result<Vec<&str>, Error> {
+        let column = self.table.column(name)?;
+        self.rows
+            .iter()
+            .map(|&row| {
+                let value = &self.table.records[row][column];
+                if value.is_empty() || value == "NA" {
+                    Err(PhenotypeError::Missing {
+                        subject: self.table.subjects[row].clone(),
+                        column: name.to_string(),
+                    }
+                    .into())
+                } else {
+                    Ok(value)
+                }
+            })
+            .collect()
+    }
+
+    /// Block ids from a column of cluster labels, e.g. family or site, with
+    /// the mapping between ids and labels, see [`BlockIds::from_labels()`].
+    pub fn block_ids(&self, column: &str) -> Result<(BlockIds, BlockLabels<String>), Error> {
+        let labels: Vec<String> = self.column(column)?.into_iter().map(String::from).collect();
+        Ok(BlockIds::from_labels(&labels)?)
+    }
+
+    /// Design matrix with an optional intercept followed by the columns for
+    /// each covariate in order. Returns an error if any covariate has missing
+    /// values or a continuous covariate has values which are not numbers.
+    pub fn design_matrix(&self, covariates: &[Covariate], intercept: bool) -> Result<Design, Error> {
+        let mut columns = Vec::new();
+        let mut names = Vec::new();
+        if intercept {
+            columns.push(vec![1.; self.n_obs()]);
+            names.push("intercept".to_string());
+        }
+        for covariate in covariates {
+            let name = covariate.name();
+            let values = self.column(name)?;
+            match covariate {
+                Covariate::Continuous(_) => {
+                    let column = values
+                        .iter()
+                        .zip(&self.rows)
+                        .map(|(value, &row)| {
+                            // Rust parses "NaN" and "inf", which would poison
+                            // the design matrix, so only accept finite values.
+                            value.parse::<f64>().ok().filter(|value| value.is_finite()).ok_or_else(|| {
+                                PhenotypeError::NotNumeric {
+                                    subject: self.table.subjects[row].clone(),
+                                    column: name.to_string(),
+                                    value: value.to_string(),
+                                }
+                            })
+                        })
+                        .collect::<Result<Vec<_>, _>>()?;
+                    columns.push(column);
+                    names.push(name.to_string());
+                }
+                Covariate::Categorical(_) => {
+                    // The first category in sorted order is the reference.
+                    let categories: BTreeSet<&str> = values.iter().copied().collect();
+                    for category in categories.into_iter().skip(1) {
+                        columns.push(values.iter().map(|&value| (value == category) as u8 as f64).collect());
+                        names.push(format!("{}_{}", name, category));
+                    }
+                }
+            }
+        }
+
+        // Gather the columns into an observations x predictors matrix.
+        let mut x = Array::zeros((self.n_obs(), columns.len()));
+        for (mut x, column) in x.columns_mut().into_iter().zip(columns) {
+            x.assign(&Array::from(column));
+        }
+        Ok(Design { x, names })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CSV: &str = "subject,site,age
+s1,A,10.5
+s2,B,NA
+s3,A,
+s4,B,inf
+s5,A,NaN
+s6,B,9
+";
+
+    fn table() -> PhenotypeTable {
+        PhenotypeTable::from_reader(CSV.as_bytes(), b',', "subject").unwrap()
+    }
+
+    // The phenotype error of a result which must be an error.
+    fn phenotype_error<T>(result: Result<T, Error>) -> PhenotypeError {
+        match result {
+            Ok(_) => panic!("expected an error"),
+            Err(Error::Phenotype(err)) => err,
+            Err(err) => panic!("unexpected error: {}", err),
+        }
+    }
+
+    #[test]
+    fn invalid_csv_is_rejected() {
+        let csv = "subject,site\ns1,A\ns2,B,extra\n";
+        let err = phenotype_error(PhenotypeTable::from_reader(csv.as_bytes(), b',', "subject"));
+        assert!(matches!(err, PhenotypeError::Csv(_)), "{:?}", err);
+    }
+
+    #[test]
+    fn missing_column_is_rejected() {
+        let err = phenotype_error(PhenotypeTable::from_reader(CSV.as_bytes(), b',', "id"));
+        assert!(matches!(&err, PhenotypeError::Column { name } if name == "id"), "{:?}", err);
+        let err = phenotype_error(table().align(&["s1"]).unwrap().column("family"));
+        assert!(matches!(&err, PhenotypeError::Column { name } if name == "family"), "{:?}", err);
+    }
+
+    #[test]
+    fn duplicate_rows_are_rejected() {
+        let csv = "subject,site\ns2,A\ns1,B\ns2,A\ns1,C\ns3,A\n";
+        let err = phenotype_error(PhenotypeTable::from_reader(csv.as_bytes(), b',', "subject"));
+        assert!(matches!(&err, PhenotypeError::DuplicateSubjects { ids } if ids == &["s1", "s2"]), "{:?}", err);
+    }
+
+    #[test]
+    fn duplicate_observations_are_rejected() {
+        let table = table();
+        let err = phenotype_error(table.align(&["s3", "s1", "s3"]));
+        assert!(matches!(&err, PhenotypeError::DuplicateObservations { ids } if ids == &["s3"]), "{:?}", err);
+    }
+
+    #[test]
+    fn unmatched_observations_are_rejected() {
+        let table = table();
+        let err = phenotype_error(table.align(&["s1", "s7", "s2", "s0"]));
+        assert!(matches!(&err, PhenotypeError::Unmatched { ids } if ids == &["s7", "s0"]), "{:?}", err);
+    }
+
+    #[test]
+    fn missing_values_are_rejected() {
+        let table = table();
+        for subject in ["s2", "s3"] {
+            let aligned = table.align(&["s1", subject]).unwrap();
+            let err = phenotype_error(aligned.column("age"));
+            assert!(
+                matches!(&err, PhenotypeError::Missing { subject: s, column } if s == subject && column == "age"),
+                "{:?}",
+                err
+            );
+        }
+    }
+
+    #[test]
+    fn non_finite_values_are_rejected() {
+        let table = table();
+        let covariates = [Covariate::Continuous("age".to_string())];
+        for (subject, value) in [("s4", "inf"), ("s5", "NaN")] {
+            let aligned = table.align(&["s1", subject]).unwrap();
+            let err = phenotype_error(aligned.design_matrix(&covariates, true));
+            assert!(
+                matches!(&err, PhenotypeError::NotNumeric { subject: s, column, value: v }
+                    if s == subject && column == "age" && v == value),
+                "{:?}",
+                err
+            );
+        }
+        let design = table.align(&["s6", "s1"]).unwrap().design_matrix(&covariates, false).unwrap();
+        assert_eq!(design.x.column(0).to_vec(), vec![9., 10.5]);
+    }
+
+    #[test]
+    fn tsv_is_detected_from_the_extension() {
+        let dir = std::env::temp_dir();
+        for (name, delimiter) in [("tsv", '\t'), ("tab", '\t'), ("csv", ',')] {
+            let path = dir.join(format!("swe-mockup-phenotype-{}.{}", std::process::id(), name));
+            std::fs::write(&path, CSV.replace(',', &delimiter.to_string())).unwrap();
+            let table = PhenotypeTable::from_path(&path, "subject");
+            std::fs::remove_file(&path).unwrap();
+            let table = table.unwrap();
+            assert_eq!(table.headers(), ["subject", "site", "age"]);
+            assert_eq!(table.subjects(), ["s1", "s2", "s3", "s4", "s5", "s6"]);
+        }
+        // A tab-separated file read as CSV has a single column.
+        let path = dir.join(format!("swe-mockup-phenotype-{}.txt", std::process::id()));
+        std::fs::write(&path, CSV.replace(',', "\t")).unwrap();
+        let err = phenotype_error(PhenotypeTable::from_path(&path, "subject"));
+        std::fs::remove_file(&path).unwrap();
+        assert!(matches!(err, PhenotypeError::Column { .. }), "{:?}", err);
+    }
+}
@@ -0,0 +1,73 @@
+//! Sandwich estimator (SwE) statistics for use in resampling tests.
+//!
+//! The benchmarks compute the full `pred x pred x feat` covariance of the
+//! regression coefficients. A resampling test only needs a test statistic for
+//! one predictor, which requires just the diagonal element of the SwE for
+//! that predictor and can be computed much more cheaply.
+
+use ndarray::{Array, ArrayView, Axis, Dim, LinalgScalar};
+use num_traits::Float;
+
+/// Group observations by block id.
+///
+/// Returns a vector with one entry per distinct block id, in ascending order
+/// of id, listing the indices of the observations in that block.
+pub fn block_indices(block_ids: ArrayView<usize, Dim<[usize; 1]>>) -> Vec<Vec<usize>> {
+    // Map each distinct block id to its observations.
+    let mut blocks = std::collections::BTreeMap::<usize, Vec<usize>>::new();
+    for (index, &block_id) in block_ids.indexed_iter() {
+        blocks.entry(block_id).or_default().push(index);
+    }
+    blocks.into_values().collect()
+}
+
+/// SwE variance of the regression coefficient for predictor `pred`, for each
+/// feature.
+///
+/// The `resid` are an observations x features matrix of residuals and
+/// `blocks` lists the observations in each block, see [`block_indices()`].
+/// This is the `[pred, pred, ..]` diagonal of the full SwE covariance.
+pub fn variance<S>(
+    x_pinv: ArrayView<S, Dim<[usize; 2]>>,
+    resid: ArrayView<S, Dim<[usize; 2]>>,
+    blocks: &[Vec<usize>],
+    pred: usize,
+) -> Array<S, Dim<[usize; 1]>>
+where
+    S: LinalgScalar,
+{
+    let x_pinv = x_pinv.row(pred);
+    let mut var = Array::zeros(resid.len_of(Axis(1)));
+    for block_indices in blocks {
+        // Half sandwich for this predictor and all features in this block.
+        let half_sandwich = x_pinv
+            .select(Axis(0), block_indices)
+            .dot(&resid.select(Axis(0), block_indices));
+        // Add this block's contribution to the diagonal of the SwE.
+        var.zip_mut_with(&half_sandwich, |var, &h| *var = *var + h * h);
+    }
+    var
+}
+
+/// SwE t-statistic for predictor `pred`, for each feature.
+///
+/// The `y` are an observations x features matrix of responses. The SwE is
+/// computed from `y` itself rather than from the residuals of the fitted
+/// model, i.e. the residuals are restricted by the null hypothesis that all
+/// coefficients are zero. This is the natural statistic when `y` are
+/// resampled residuals, as in a sign-flipping or permutation test.
+pub fn t_stat<S>(
+    x_pinv: ArrayView<S, Dim<[usize; 2]>>,
+    y: ArrayView<S, Dim<[usize; 2]>>,
+    blocks: &[Vec<usize>],
+    pred: usize,
+) -> Array<S, Dim<[usize; 1]>>
+where
+    S: LinalgScalar + Float,
+{
+    // Regression coefficient for this predictor.
+    let beta = x_pinv.row(pred).dot(&y);
+    // Divide by the standard error.
+    let var = variance(x_pinv, y, blocks, pred);
+    beta / var.mapv(Float::sqrt)
+}
@@ -0,0 +1,323 @@
+//! Tables of per-feature statistics as Arrow IPC or Parquet files, with the
+//! `arrow` cargo feature.
+//!
+//! [`FeatureStats`] holds the results of testing one predictor at every
+//! feature. They are written with one row per feature, so that they can be
+//! loaded directly as a data frame, e.g. with `pandas.read_feather()` or
+//! `polars.read_ipc()` for Arrow IPC files and `pandas.read_parquet()` or
+//! `polars.read_parquet()` for Parquet files. The columns are:
+//!
+//! * `feature`: index of the feature, from zero;
+//! * `node_a` and `node_b`: nodes at either end of the edge, if the features
+//!   are the edges of a connectome;
+//! * `beta_0`, `beta_1`, ...: estimated coefficient of each predictor;
+//! * `se`, `t` and `p`: standard error, t-statistic and p-value of the
+//!   tested predictor;
+//! * `p_adj`: adjusted p-value, if any.
+//!
+//! The index of the tested predictor is stored in the metadata of the schema
+//! under the key `pred`.
+//!
+//! Example:
+//! ```no_run
+//! use std::fs::File;
+//! use swe_mockup::exchange::Shuffle;
+//! use swe_mockup::maxstat::Tail;
+//! use swe_mockup::permute::{PermutationScheme, PermutationTest};
+//! use swe_mockup::table::FeatureStats;
+//! use swe_mockup::{swe, MockData, MockParams};
+//! let mut params = MockParams::default();
+//! params.design.keep_model = true;
+//! let edges = params.features.edges();
+//! let mock_data = MockData::<f64>::from_params(params);
+//! let blocks = swe::block_indices(mock_data.block_ids.view());
+//! let pred = 1;
+//! let beta = mock_data.x_pinv.dot(mock_data.y.as_ref().unwrap());
+//! let se = swe::variance(mock_data.x_pinv.view(), mock_data.resid.view(), &blocks, pred).mapv(f64::sqrt);
+//! let t = &beta.row(pred) / &se;
+//! let mut test = PermutationTest::new(
+//!     mock_data.exchangeability_tree(Shuffle::WithinBlock),
+//!     PermutationScheme::default(),
+//!     t.clone(),
+//!     Tail::Both,
+//!     42,
+//! )?;
+//! test.run(1000, mock_data.resid.view(), |y| swe::t_stat(mock_data.x_pinv.view(), y, &blocks, pred));
+//! let stats = FeatureStats {
+//!     beta,
+//!     pred,
+//!     se,
+//!     t,
+//!     p: test.p_uncorrected(),
+//!     p_adj: Some(test.p_fwer()),
+//!     edges,
+//! };
+//! stats.save_parquet_file(File::create("stats.parquet")?)?;
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+
+use crate::Error;
+use arrow_array::{ArrayRef, Float64Array, RecordBatch, UInt64Array};
+use arrow_ipc::writer::FileWriter;
+use arrow_schema::{ArrowError, DataType, Field, Schema};
+use ndarray::{Array, Dim};
+use parquet::arrow::ArrowWriter;
+use parquet::basic::Compression;
+use parquet::file::properties::WriterProperties;
+use std::collections::HashMap;
+use std::io::Write;
+use std::sync::Arc;
+
+/// Statistics of a test of one predictor at each feature.
+#[derive(Clone, Debug)]
+pub struct FeatureStats {
+    /// Predictors x features matrix of estimated regression coefficients
+    pub beta: Array<f64, Dim<[usize; 2]>>,
+    /// Index of the tested predictor
+    pub pred: usize,
+    /// Standard error of the coefficient of the tested predictor, e.g. the
+    /// square root of [`swe::variance()`](crate::swe::variance)
+    pub se: Array<f64, Dim<[usize; 1]>>,
+    /// t-statistic of the tested predictor
+    pub t: Array<f64, Dim<[usize; 1]>>,
+    /// Uncorrected p-value, e.g. from
+    /// [`PermutationTest::p_uncorrected()`](crate::permute::PermutationTest::p_uncorrected)
+    pub p: Array<f64, Dim<[usize; 1]>>,
+    /// Adjusted p-value, if any, e.g. from
+    /// [`PermutationTest::p_fwer()`](crate::permute::PermutationTest::p_fwer)
+    /// or [`adjust()`](crate::adjust::adjust)
+    pub p_adj: Option<Array<f64, Dim<[usize; 1]>>>,
+    /// Nodes at either end of each feature, if the features are the edges of a
+    /// connectome, see [`Features::edges()`](crate::Features::edges)
+    pub edges: Option<Vec<(usize, usize)>>,
+}
+impl FeatureStats {
+    /// Number of features, the number of columns of `beta`.
+    pub fn n_feat(&self) -> usize {
+        self.beta.ncols()
+    }
+
+    /// Arrow record batch with one row per feature, with the columns
+    /// described in the [module documentation](self). Returns an error if the
+    /// tested predictor is not a row of `beta` or any statistic does not have
+    /// one value per feature.
+    pub fn to_record_batch(&self) -> Result<RecordBatch, Error> {
+        // Check the predictor and the lengths of the columns, so that errors
+        // name the offending statistic.
+        let n_feat = self.n_feat();
+        if self.pred >= self.beta.nrows() {
+            return Err(invalid(format!(
+                "Tested predictor {} is out of range for {} predictors.",
+                self.pred,
+                self.beta.nrows()
+            )));
+        }
+        let mut lengths = vec![("se", self.se.len()), ("t", self.t.len()), ("p", self.p.len())];
+        if let Some(p_adj) = &self.p_adj {
+            lengths.push(("p_adj", p_adj.len()));
+        }
+        if let Some(edges) = &self.edges {
+            lengths.push(("edges", edges.len()));
+        }
+        if let Some((name, len)) = lengths.into_iter().find(|&(_, len)| len != n_feat) {
+            return Err(invalid(format!(
+                "Statistic {} has {} values but there are {} features.",
+                name, len, n_feat
+            )));
+        }
+
+        // Gather the columns and their fields in order.
+        let mut fields = Vec::new();
+        let mut columns: Vec<ArrayRef> = Vec::new();
+        fields.push(Field::new("feature", DataType::UInt64, false));
+        columns.push(Arc::new(UInt64Array::from_iter_values(0..n_feat as u64)));
+        if let Some(edges) = &self.edges {
+            fields.push(Field::new("node_a", DataType::UInt64, false));
+            columns.push(Arc::new(UInt64Array::from_iter_values(edges.iter().map(|&(a, _)| a as u64))));
+            fields.push(Field::new("node_b", DataType::UInt64, false));
+            columns.push(Arc::new(UInt64Array::from_iter_values(edges.iter().map(|&(_, b)| b as u64))));
+        }
+        for (pred, beta) in self.beta.rows().into_iter().enumerate() {
+            fields.push(Field::new(format!("beta_{}", pred), DataType::Float64, false));
+            columns.push(Arc::new(Float64Array::from_iter_values(beta.iter().copied())));
+        }
+        let mut stats = vec![("se", &self.se), ("t", &self.t), ("p", &self.p)];
+        if let Some(p_adj) = &self.p_adj {
+            stats.push(("p_adj", p_adj));
+        }
+        for (name, stat) in stats {
+            fields.push(Field::new(name, DataType::Float64, false));
+            columns.push(Arc::new(Float64Array::from_iter_values(stat.iter().copied())));
+        }
+
+        // Record the tested predictor in the metadata.
+        let metadata = HashMap::from([("pred".to_string(), self.pred.to_string())]);
+        let schema = Schema::new(fields).with_metadata(metadata);
+        Ok(RecordBatch::try_new(Arc::new(schema), columns)?)
+    }
+
+    /// Save as an Arrow IPC file, also known as Feather version 2.
+    ///
+    /// Example, testing the second of two predictors at three features:
+    /// ```
+    /// use ndarray::array;
+    /// use swe_mockup::table::FeatureStats;
+    /// let stats = FeatureStats {
+    ///     beta: array![[0.1, 0.2, 0.3], [1.5, -0.2, 0.0]],
+    ///     pred: 1,
+    ///     se: array![0.5, 0.4, 0.3],
+    ///     t: array![3.0, -0.5, 0.0],
+    ///     p: array![0.01, 0.6, 1.0],
+    ///     p_adj: None,
+    ///     edges: None,
+    /// };
+    /// let mut ipc = Vec::new();
+    /// stats.save_ipc_file(&mut ipc)?;
+    /// assert!(ipc.starts_with(b"ARROW1"));
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    pub fn save_ipc_file<W: Write>(&self, w: W) -> Result<(), Error> {
+        let batch = self.to_record_batch()?;
+        let mut writer = FileWriter::try_new_buffered(w, &batch.schema())?;
+        writer.write(&batch)?;
+        writer.finish()?;
+        Ok(())
+    }
+
+    /// Save as a Parquet file, compressed with snappy.
+    ///
+    /// Example, testing the first of two predictors at the three edges of a
+    /// connectome of three nodes:
+    /// ```
+    /// use ndarray::array;
+    /// use swe_mockup::table::FeatureStats;
+    /// let stats = FeatureStats {
+    ///     beta: array![[0.1, 0.2, 0.3], [1.5, -0.2, 0.0]],
+    ///     pred: 0,
+    ///     se: array![0.05, 0.1, 0.3],
+    ///     t: array![2.0, 2.0, 1.0],
+    ///     p: array![0.04, 0.05, 0.3],
+    ///     p_adj: Some(array![0.12, 0.1, 0.3]),
+    ///     edges: Some(vec![(0, 1), (0, 2), (1, 2)]),
+    /// };
+    /// let mut parquet = Vec::new();
+    /// stats.save_parquet_file(&mut parquet)?;
+    /// assert!(parquet.starts_with(b"PAR1"));
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    pub fn save_parquet_file<W: Write + Send>(&self, w: W) -> Result<(), Error> {
+        let batch = self.to_record_batch()?;
+        let props = WriterProperties::builder().set_compression(Compression::SNAPPY).build();
+        let mut writer = ArrowWriter::try_new(w, batch.schema(), Some(props))?;
+        writer.write(&batch)?;
+        writer.close()?;
+        Ok(())
+    }
+}
+
+// Error for statistics which do not fit in a table.
+fn invalid(msg: String) -> Error {
+    Error::Arrow(ArrowError::InvalidArgumentError(msg))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use arrow_array::Array as _;
+    use arrow_ipc::reader::FileReader;
+    use ndarray::array;
+    use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+    use std::fs::File;
+    use std::io::Cursor;
+
+    // Statistics of the second of two predictors at the three edges of a
+    // connectome of three nodes.
+    fn stats() -> FeatureStats {
+        FeatureStats {
+            beta: array![[0.1, 0.2, 0.3], [1.5, -0.2, 0.0]],
+            pred: 1,
+            se: array![0.5, 0.4, 0.3],
+            t: array![3.0, -0.5, 0.0],
+            p: array![0.01, 0.6, 1.0],
+            p_adj: Some(array![0.03, 1.0, 1.0]),
+            edges: Some(vec![(0, 1), (0, 2), (1, 2)]),
+        }
+    }
+
+    // Check the columns, their types and values, and the metadata of a
+    // record batch read back from a file with the given schema.
+    fn check_batch(schema: &Schema, batch: &RecordBatch) {
+        let names: Vec<&str> = schema.fields().iter().map(|field| field.name().as_str()).collect();
+        assert_eq!(names, ["feature", "node_a", "node_b", "beta_0", "beta_1", "se", "t", "p", "p_adj"]);
+        assert_eq!(schema.metadata().get("pred").map(String::as_str), Some("1"));
+        assert_eq!(batch.num_rows(), 3);
+        let uint64 = |name: &str| -> Vec<u64> {
+            let column = batch.column_by_name(name).unwrap();
+            assert_eq!(column.data_type(), &DataType::UInt64, "{}", name);
+            column.as_any().downcast_ref::<UInt64Array>().unwrap().values().to_vec()
+        };
+        let float64 = |name: &str| -> Vec<f64> {
+            let column = batch.column_by_name(name).unwrap();
+            assert_eq!(column.data_type(), &DataType::Float64, "{}", name);
+            column.as_any().downcast_ref::<Float64Array>().unwrap().values().to_vec()
+        };
+        assert_eq!(uint64("feature"), [0, 1, 2]);
+        assert_eq!(uint64("node_a"), [0, 0, 1]);
+        assert_eq!(uint64("node_b"), [1, 2, 2]);
+        assert_eq!(float64("beta_0"), [0.1, 0.2, 0.3]);
+        assert_eq!(float64("beta_1"), [1.5, -0.2, 0.0]);
+        assert_eq!(float64("se"), [0.5, 0.4, 0.3]);
+        assert_eq!(float64("t"), [3.0, -0.5, 0.0]);
+        assert_eq!(float64("p"), [0.01, 0.6, 1.0]);
+        assert_eq!(float64("p_adj"), [0.03, 1.0, 1.0]);
+    }
+
+    #[test]
+    fn ipc_round_trip() {
+        let mut ipc = Vec::new();
+        stats().save_ipc_file(&mut ipc).unwrap();
+        let reader = FileReader::try_new(Cursor::new(ipc), None).unwrap();
+        let schema = reader.schema();
+        let batches: Vec<RecordBatch> = reader.collect::<Result<_, _>>().unwrap();
+        assert_eq!(batches.len(), 1);
+        check_batch(&schema, &batches[0]);
+    }
+
+    #[test]
+    fn parquet_round_trip() {
+        let path = std::env::temp_dir().join(format!("swe-mockup-stats-{}.parquet", std::process::id()));
+        stats().save_parquet_file(File::create(&path).unwrap()).unwrap();
+        let builder = ParquetRecordBatchReaderBuilder::try_new(File::open(&path).unwrap()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        // Only the schema of the builder keeps the metadata.
+        let schema = builder.schema().clone();
+        let reader = builder.build().unwrap();
+        let batches: Vec<RecordBatch> = reader.collect::<Result<_, _>>().unwrap();
+        assert_eq!(batches.len(), 1);
+        check_batch(&schema, &batches[0]);
+    }
+
+    #[test]
+    fn optional_columns_are_left_out() {
+        let stats = FeatureStats {
+            p_adj: None,
+            edges: None,
+            ..stats()
+        };
+        let batch = stats.to_record_batch().unwrap();
+        let schema = batch.schema();
+        let names: Vec<&str> = schema.fields().iter().map(|field| field.name().as_str()).collect();
+        assert_eq!(names, ["feature", "beta_0", "beta_1", "se", "t", "p"]);
+    }
+
+    #[test]
+    fn mismatched_statistics_are_rejected() {
+        let short_se = FeatureStats {
+            se: array![0.5, 0.4],
+            ..stats()
+        };
+        assert!(matches!(short_se.to_record_batch(), Err(Error::Arrow(ArrowError::InvalidArgumentError(_)))));
+        let bad_pred = FeatureStats { pred: 2, ..stats() };
+        assert!(matches!(bad_pred.to_record_batch(), Err(Error::Arrow(ArrowError::InvalidArgumentError(_)))));
+    }
+}